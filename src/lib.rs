@@ -1,17 +1,30 @@
 #![doc = include_str!("../README.md")]
 
 use sqlparser::ast::{
-    Assignment, AssignmentTarget, ConflictTarget, Delete, Distinct, DoUpdate, Expr, GroupByExpr,
-    Ident, Insert, JoinConstraint, JoinOperator, LimitClause, ObjectName, ObjectNamePart, Offset,
-    OnConflict, OnConflictAction, OnInsert, OrderBy, OrderByKind, Parens, Query, SelectItem,
-    SetExpr, Statement, TableAliasColumnDef, TableFactor, Update, Value, ValueWithSpan, VisitMut,
-    VisitorMut,
+    AlterTable, Assignment, AssignmentTarget, BinaryOperator, ConflictTarget, CopySource,
+    CopyTarget, CreateIndex, CreateTable, CreateView, Delete, Distinct, DoUpdate, Expr, Fetch,
+    FromTable, GroupByExpr, GroupByWithModifier, Ident, Insert, JoinConstraint, JoinOperator,
+    LimitClause, Lock, Merge, MergeAction, MergeInsertExpr, MergeInsertKind, ObjectName,
+    ObjectNamePart, ObjectType, Offset, OffsetRows, OnConflict, OnConflictAction, OnInsert,
+    OrderBy, OrderByKind, Parens, Query, Select, SelectItem, SelectItemQualifiedWildcardKind, Set,
+    SetExpr, SetOperator, SetQuantifier, SqliteOnConflict, Statement, TableAlias,
+    TableAliasColumnDef, TableFactor, TableObject, TableSample, TableSampleBucket, TableSampleKind,
+    TableSampleQuantity, TableSampleSeed, TableWithJoins, Top, TopQuantity, Truncate, Update,
+    UpdateTableFromKind, Value, ValueWithSpan, Visit, VisitMut, Visitor, VisitorMut,
 };
-use sqlparser::dialect::{Dialect, GenericDialect};
-use sqlparser::parser::Parser;
-use sqlparser::tokenizer::Span;
-use std::collections::HashMap;
-use std::ops::ControlFlow;
+use sqlparser::dialect::{
+    AnsiDialect, ClickHouseDialect, Dialect, GenericDialect, MsSqlDialect, MySqlDialect,
+    OracleDialect, PostgreSqlDialect, RedshiftSqlDialect, SnowflakeDialect,
+};
+use sqlparser::keywords::{ALL_KEYWORDS, ALL_KEYWORDS_INDEX, Keyword};
+use sqlparser::parser::{Parser, ParserError};
+use sqlparser::tokenizer::{Location, Span, Token, Tokenizer};
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::iter;
+use std::ops::{ControlFlow, Range};
+use std::time::Duration;
 
 /// Fingerprint a single SQL string.
 ///
@@ -41,371 +54,6748 @@ pub fn fingerprint_one(input: &str, dialect: Option<&dyn Dialect>) -> String {
 /// assert_eq!(result, vec!["SELECT ... FROM c", "SELECT ... FROM d"]);
 /// ```
 pub fn fingerprint_many(input: Vec<&str>, dialect: Option<&dyn Dialect>) -> Vec<String> {
-    let dialect = dialect.unwrap_or(&GenericDialect {});
+    let mut fingerprinter = Fingerprinter::new();
+    if let Some(dialect) = dialect {
+        fingerprinter = fingerprinter.dialect(dialect);
+    }
+    fingerprinter.fingerprint_many(input)
+}
 
-    let mut visitor = FingerprintingVisitor::new();
+/// A configurable fingerprinter, for when the defaults of [`fingerprint_one`] and
+/// [`fingerprint_many`] aren't enough.
+///
+/// # Example
+/// ```
+/// use sql_fingerprint::{Fingerprinter, PlaceholderStyle};
+/// use sqlparser::dialect::PostgreSqlDialect;
+///
+/// let fingerprinter = Fingerprinter::new()
+///     .dialect(&PostgreSqlDialect {})
+///     .placeholder_style(PlaceholderStyle::QuestionMark);
+/// let result = fingerprinter.fingerprint_one("SELECT * FROM generate_series($1, $2)");
+/// assert_eq!(result, "SELECT * FROM generate_series(?, ?)");
+/// ```
+pub struct Fingerprinter<'a> {
+    dialect: &'a dyn Dialect,
+    placeholder_style: Option<PlaceholderStyle>,
+    relation_name_rules: Vec<RewriteRule<'a>>,
+    schema_qualification: Option<SchemaQualification>,
+    anonymize_aliases: bool,
+    identifier_case: Option<IdentifierCase>,
+    lowercase_identifiers: bool,
+    unquote_non_ascii_identifiers: bool,
+    canonicalize_syntax: bool,
+    remove_redundant_parentheses: bool,
+    collapse_union_all: bool,
+    sort_commutative_operands: bool,
+}
 
-    input
-        .iter()
-        .map(|sql| match Parser::parse_sql(dialect, sql) {
-            Ok(mut ast) => {
-                for stmt in &mut ast {
-                    let _ = stmt.visit(&mut visitor);
-                }
+type RewriteRule<'a> = Box<dyn Fn(&str) -> Option<String> + 'a>;
 
-                ast.into_iter()
-                    .map(|stmt| stmt.to_string())
-                    .collect::<Vec<_>>()
-                    .join(" ")
-            }
-            Err(_) => sql.to_string(),
-        })
-        .collect()
+impl fmt::Debug for Fingerprinter<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Fingerprinter")
+            .field("dialect", &self.dialect)
+            .field("placeholder_style", &self.placeholder_style)
+            .field("relation_name_rules", &self.relation_name_rules.len())
+            .field("schema_qualification", &self.schema_qualification)
+            .field("anonymize_aliases", &self.anonymize_aliases)
+            .field("identifier_case", &self.identifier_case)
+            .field("lowercase_identifiers", &self.lowercase_identifiers)
+            .field(
+                "unquote_non_ascii_identifiers",
+                &self.unquote_non_ascii_identifiers,
+            )
+            .field("canonicalize_syntax", &self.canonicalize_syntax)
+            .field(
+                "remove_redundant_parentheses",
+                &self.remove_redundant_parentheses,
+            )
+            .field("collapse_union_all", &self.collapse_union_all)
+            .field("sort_commutative_operands", &self.sort_commutative_operands)
+            .finish()
+    }
 }
 
-struct FingerprintingVisitor {
-    savepoint_ids: HashMap<String, String>,
+impl Default for Fingerprinter<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
-impl FingerprintingVisitor {
-    fn new() -> Self {
-        FingerprintingVisitor {
-            savepoint_ids: HashMap::new(),
+impl<'a> Fingerprinter<'a> {
+    /// Create a fingerprinter with the default options, using the generic SQL dialect.
+    pub fn new() -> Self {
+        Fingerprinter {
+            dialect: &GenericDialect {},
+            placeholder_style: None,
+            relation_name_rules: vec![],
+            schema_qualification: None,
+            anonymize_aliases: false,
+            identifier_case: None,
+            lowercase_identifiers: false,
+            unquote_non_ascii_identifiers: true,
+            canonicalize_syntax: false,
+            remove_redundant_parentheses: false,
+            collapse_union_all: false,
+            sort_commutative_operands: false,
         }
     }
 
-    fn visit_select(&mut self, select: &mut sqlparser::ast::Select) {
-        if !select.projection.is_empty() {
-            if let Some(item) = select.projection.first_mut() {
-                match item {
-                    SelectItem::UnnamedExpr(_) | SelectItem::ExprWithAlias { .. } => {
-                        *item = SelectItem::UnnamedExpr(placeholder_value());
+    /// Set the SQL dialect used to parse input.
+    pub fn dialect(mut self, dialect: &'a dyn Dialect) -> Self {
+        self.dialect = dialect;
+        self
+    }
+
+    /// Rewrite bind parameters that survive fingerprinting to the given style.
+    /// By default, they are left as written.
+    pub fn placeholder_style(mut self, style: PlaceholderStyle) -> Self {
+        self.placeholder_style = Some(style);
+        self
+    }
+
+    /// Add a rule to rewrite table and schema names, such as for sharded or generated tables.
+    /// The rule is called with each unquoted part of a relation name, and of the qualifiers of
+    /// column references, and returns a replacement or `None` to leave the part unchanged.
+    /// When several rules are added, the first one to return a replacement wins.
+    ///
+    /// # Example
+    /// ```
+    /// use sql_fingerprint::Fingerprinter;
+    ///
+    /// let fingerprinter = Fingerprinter::new().rewrite_relation_names(|name| {
+    ///     let suffix = name.strip_prefix("events_")?;
+    ///     suffix
+    ///         .chars()
+    ///         .all(|c| c.is_ascii_digit() || c == '_')
+    ///         .then(|| "events_*".to_string())
+    /// });
+    /// let result = fingerprinter.fingerprint_many(vec![
+    ///     "SELECT * FROM events_2026_09",
+    ///     "SELECT * FROM events_2026_10",
+    /// ]);
+    /// assert_eq!(result, vec!["SELECT * FROM events_*", "SELECT * FROM events_*"]);
+    /// ```
+    pub fn rewrite_relation_names<F>(mut self, rule: F) -> Self
+    where
+        F: Fn(&str) -> Option<String> + 'a,
+    {
+        self.relation_name_rules.push(Box::new(rule));
+        self
+    }
+
+    /// Normalize schema and catalog qualifiers on relation names and compound identifiers.
+    /// By default, they are left as written.
+    ///
+    /// # Example
+    /// ```
+    /// use sql_fingerprint::{Fingerprinter, SchemaQualification};
+    ///
+    /// let fingerprinter = Fingerprinter::new()
+    ///     .schema_qualification(SchemaQualification::StripDefault("public".to_string()));
+    /// let result = fingerprinter.fingerprint_many(vec![
+    ///     "SELECT * FROM users",
+    ///     "SELECT * FROM public.users",
+    ///     "SELECT * FROM \"mydb\".\"public\".\"users\"",
+    /// ]);
+    /// assert_eq!(result, vec!["SELECT * FROM users"; 3]);
+    /// ```
+    pub fn schema_qualification(mut self, qualification: SchemaQualification) -> Self {
+        self.schema_qualification = Some(qualification);
+        self
+    }
+
+    /// Rename table aliases, CTE names, and derived table aliases to 't1', 't2', etc., in order
+    /// of definition within each SQL string, and rewrite references to them to match.
+    /// Names of tables in the SQL string are skipped, so that references stay unambiguous.
+    /// This stabilizes fingerprints for ORM-generated aliases, like Django's `U0` or
+    /// SQLAlchemy's `anon_1`, whose numbering changes with unrelated parts of the query.
+    ///
+    /// # Example
+    /// ```
+    /// use sql_fingerprint::Fingerprinter;
+    ///
+    /// let fingerprinter = Fingerprinter::new().anonymize_aliases(true);
+    /// let result = fingerprinter.fingerprint_one("SELECT U1.* FROM users U1");
+    /// assert_eq!(result, "SELECT t1.* FROM users t1");
+    /// ```
+    pub fn anonymize_aliases(mut self, anonymize: bool) -> Self {
+        self.anonymize_aliases = anonymize;
+        self
+    }
+
+    /// Set how the database treats the case of unquoted identifiers, which determines when
+    /// quotes can be removed without changing meaning.
+    /// By default, this is inferred from the dialect.
+    ///
+    /// # Example
+    /// ```
+    /// use sql_fingerprint::Fingerprinter;
+    /// use sqlparser::dialect::PostgreSqlDialect;
+    ///
+    /// let fingerprinter = Fingerprinter::new().dialect(&PostgreSqlDialect {});
+    /// let result = fingerprinter.fingerprint_one("SELECT * FROM \"MyTable\" JOIN \"other\"");
+    /// assert_eq!(result, "SELECT * FROM \"MyTable\" JOIN other");
+    /// ```
+    pub fn identifier_case(mut self, case: IdentifierCase) -> Self {
+        self.identifier_case = Some(case);
+        self
+    }
+
+    /// Lowercase unquoted identifiers, when the identifier case is case-insensitive, so that
+    /// differently-cased references to the same object share a fingerprint.
+    /// By default, identifiers keep their case.
+    pub fn lowercase_identifiers(mut self, lowercase: bool) -> Self {
+        self.lowercase_identifiers = lowercase;
+        self
+    }
+
+    /// Allow removing quotes from identifiers containing non-ASCII letters and digits.
+    /// Enabled by default.
+    pub fn unquote_non_ascii_identifiers(mut self, unquote: bool) -> Self {
+        self.unquote_non_ascii_identifiers = unquote;
+        self
+    }
+
+    /// Rewrite equivalent syntax variants to a single form, so that queries written by different
+    /// ORMs or people share a fingerprint:
+    ///
+    /// * `INNER JOIN` becomes `JOIN`, and `LEFT OUTER JOIN` and `RIGHT OUTER JOIN` drop `OUTER`.
+    /// * `UNION DISTINCT`, `EXCEPT DISTINCT`, and `INTERSECT DISTINCT` drop `DISTINCT`.
+    /// * Table aliases always use `AS`, except in Oracle, where they never do.
+    /// * `START TRANSACTION` and `BEGIN TRANSACTION` become `BEGIN`.
+    /// * `END` becomes `COMMIT`.
+    /// * `TOP n` and `FETCH FIRST n ROWS ONLY` become `LIMIT n`, unless they use `PERCENT` or
+    ///   `WITH TIES`.
+    ///
+    /// `!=` is always rewritten to `<>`.
+    ///
+    /// # Example
+    /// ```
+    /// use sql_fingerprint::Fingerprinter;
+    ///
+    /// let fingerprinter = Fingerprinter::new().canonicalize_syntax(true);
+    /// let result = fingerprinter.fingerprint_many(vec![
+    ///     "SELECT a FROM b x INNER JOIN c y ON x.id = y.id",
+    ///     "SELECT a FROM b AS x JOIN c AS y ON x.id = y.id",
+    /// ]);
+    /// assert_eq!(result, vec!["SELECT ... FROM b AS x JOIN c AS y ON ..."; 2]);
+    /// ```
+    pub fn canonicalize_syntax(mut self, canonicalize: bool) -> Self {
+        self.canonicalize_syntax = canonicalize;
+        self
+    }
+
+    /// Remove parentheses that don't change meaning: around queries and `SELECT` set operation
+    /// operands without their own `ORDER BY`, `LIMIT`, and so on, and around single values,
+    /// identifiers, function calls, and subqueries.
+    ///
+    /// # Example
+    /// ```
+    /// use sql_fingerprint::Fingerprinter;
+    ///
+    /// let fingerprinter = Fingerprinter::new().remove_redundant_parentheses(true);
+    /// let result = fingerprinter.fingerprint_one("(SELECT a FROM b) UNION (SELECT a FROM c)");
+    /// assert_eq!(result, "SELECT ... FROM b UNION SELECT ... FROM c");
+    /// ```
+    pub fn remove_redundant_parentheses(mut self, remove: bool) -> Self {
+        self.remove_redundant_parentheses = remove;
+        self
+    }
+
+    /// Collapse consecutive identical `UNION ALL` branches into one, so that batched queries
+    /// fingerprint the same whatever the batch size.
+    ///
+    /// # Example
+    /// ```
+    /// use sql_fingerprint::Fingerprinter;
+    ///
+    /// let fingerprinter = Fingerprinter::new().collapse_union_all(true);
+    /// let result = fingerprinter.fingerprint_one(
+    ///     "SELECT a FROM b WHERE c = 1 UNION ALL SELECT a FROM b WHERE c = 2 UNION ALL SELECT a FROM b WHERE c = 3",
+    /// );
+    /// assert_eq!(result, "SELECT ... FROM b WHERE ...");
+    /// ```
+    pub fn collapse_union_all(mut self, collapse: bool) -> Self {
+        self.collapse_union_all = collapse;
+        self
+    }
+
+    /// Sort the operands of commutative constructs that survive elision, such as `AND` and
+    /// `OR` chains, equality comparisons, comma-separated tables in `FROM`, and the columns of
+    /// tuple `IN` lists, so that the order they were written in doesn't matter.
+    /// With [`anonymize_aliases`](Self::anonymize_aliases), aliases are numbered in the sorted
+    /// order.
+    ///
+    /// # Example
+    /// ```
+    /// use sql_fingerprint::Fingerprinter;
+    ///
+    /// let fingerprinter = Fingerprinter::new().sort_commutative_operands(true);
+    /// let result = fingerprinter.fingerprint_many(vec![
+    ///     "SELECT a FROM b, c GROUP BY a HAVING COUNT(*) > 1 AND MAX(d) = 2",
+    ///     "SELECT a FROM c, b GROUP BY a HAVING 2 = MAX(d) AND COUNT(*) > 1",
+    /// ]);
+    /// assert_eq!(
+    ///     result,
+    ///     vec!["SELECT ... FROM b, c GROUP BY ... HAVING COUNT(*) > 1 AND MAX(d) = 2"; 2],
+    /// );
+    /// ```
+    pub fn sort_commutative_operands(mut self, sort: bool) -> Self {
+        self.sort_commutative_operands = sort;
+        self
+    }
+
+    /// Fingerprint a single SQL string.
+    ///
+    /// Unparsable SQL is returned as-is.
+    pub fn fingerprint_one(&self, input: &str) -> String {
+        self.fingerprint_many(vec![input]).join(" ")
+    }
+
+    /// Fingerprint multiple SQL strings.
+    /// Doing so for a batch of strings allows sharing some state, such as savepoint ID aliases.
+    ///
+    /// Unparsable SQL is returned as-is.
+    pub fn fingerprint_many(&self, input: Vec<&str>) -> Vec<String> {
+        let mut visitor = FingerprintingVisitor::new(self);
+
+        input
+            .iter()
+            .map(|sql| match parse_sql(self.dialect, sql) {
+                Ok(mut ast) => self.fingerprint_statements(&mut visitor, &mut ast).0,
+                Err(_) => sql.to_string(),
+            })
+            .collect()
+    }
+
+    /// Fingerprint a single SQL string, also returning the original text of each elided `...`.
+    ///
+    /// # Example
+    /// ```
+    /// use sql_fingerprint::Fingerprinter;
+    ///
+    /// let result = Fingerprinter::new()
+    ///     .fingerprint_one_with_values("SELECT a, b FROM c WHERE d = 'x' LIMIT 10");
+    /// assert_eq!(result.fingerprint, "SELECT ... FROM c WHERE ... LIMIT ...");
+    /// assert_eq!(result.values, vec!["a, b", "d = 'x'", "10"]);
+    /// ```
+    pub fn fingerprint_one_with_values(&self, input: &str) -> FingerprintWithValues {
+        self.fingerprint_many_with_values(vec![input])
+            .pop()
+            .expect("one fingerprint per input")
+    }
+
+    /// Fingerprint multiple SQL strings, like [`Fingerprinter::fingerprint_many`], also
+    /// returning the original text of each elided `...`.
+    ///
+    /// Unparsable SQL is returned as-is, with no values.
+    pub fn fingerprint_many_with_values(&self, input: Vec<&str>) -> Vec<FingerprintWithValues> {
+        let mut visitor = FingerprintingVisitor::new(self);
+        visitor.elided = Some(vec![]);
+
+        input
+            .iter()
+            .map(|sql| match parse_sql(self.dialect, sql) {
+                Ok(mut ast) => {
+                    let (fingerprint, values) = self.fingerprint_statements(&mut visitor, &mut ast);
+                    FingerprintWithValues {
+                        fingerprint,
+                        values,
                     }
-                    _ => {}
                 }
-            }
-            select.projection.truncate(1);
-        }
+                Err(_) => FingerprintWithValues {
+                    fingerprint: sql.to_string(),
+                    values: vec![],
+                },
+            })
+            .collect()
+    }
 
-        if let Some(Distinct::On(exprs)) = &mut select.distinct
-            && !exprs.is_empty()
-        {
-            *exprs = vec![placeholder_value()];
-        };
+    /// Fingerprint and analyze a single SQL string.
+    ///
+    /// # Example
+    /// ```
+    /// use sql_fingerprint::{Fingerprinter, TableReference, TableRole};
+    ///
+    /// let analysis = Fingerprinter::new().analyze_one("INSERT INTO a SELECT * FROM b JOIN c ON b.id = c.id");
+    /// assert_eq!(analysis.fingerprint, "INSERT INTO a SELECT * FROM b JOIN c ON ...");
+    /// assert_eq!(
+    ///     analysis.tables,
+    ///     vec![
+    ///         TableReference::new("a", TableRole::Write),
+    ///         TableReference::new("b", TableRole::Read),
+    ///         TableReference::new("c", TableRole::Read),
+    ///     ],
+    /// );
+    /// ```
+    pub fn analyze_one(&self, input: &str) -> Analysis {
+        self.analyze_many(vec![input])
+            .pop()
+            .expect("one analysis per input")
+    }
 
-        for table_with_joins in &mut select.from {
-            for join in &mut table_with_joins.joins {
-                match &mut join.join_operator {
-                    JoinOperator::Join(constraint)
-                    | JoinOperator::Inner(constraint)
-                    | JoinOperator::Left(constraint)
-                    | JoinOperator::LeftOuter(constraint)
-                    | JoinOperator::Right(constraint)
-                    | JoinOperator::RightOuter(constraint)
-                    | JoinOperator::FullOuter(constraint)
-                    | JoinOperator::Semi(constraint)
-                    | JoinOperator::LeftSemi(constraint)
-                    | JoinOperator::RightSemi(constraint)
-                    | JoinOperator::Anti(constraint)
-                    | JoinOperator::LeftAnti(constraint)
-                    | JoinOperator::RightAnti(constraint) => {
-                        if let JoinConstraint::On(expr) = constraint {
-                            *expr = placeholder_value();
+    /// Fingerprint and analyze multiple SQL strings, sharing state like
+    /// [`Fingerprinter::fingerprint_many`].
+    ///
+    /// Unparsable SQL is returned as-is as the fingerprint, with its kind guessed from its
+    /// leading keywords, and no other analysis.
+    pub fn analyze_many(&self, input: Vec<&str>) -> Vec<Analysis> {
+        let mut visitor = FingerprintingVisitor::new(self);
+
+        input
+            .iter()
+            .map(|sql| match parse_sql(self.dialect, sql) {
+                Ok(mut ast) => {
+                    // Analyze before fingerprinting, which elides parts of the statements.
+                    let classifications: Vec<_> = ast.iter().map(classify_statement).collect();
+                    let statement_tables = self.collect_tables(&ast);
+                    let mut tables: Vec<TableReference> = vec![];
+                    for table in statement_tables.iter().flatten() {
+                        if !tables.contains(table) {
+                            tables.push(table.clone());
                         }
                     }
-                    _ => {}
+                    let columns = self.collect_columns(&ast);
+                    let lineage = self.collect_lineage(&ast, &statement_tables);
+                    let mut metrics = collect_metrics(&ast);
+                    let values = count_values(&ast);
+                    let (fingerprint, _) = self.fingerprint_statements(&mut visitor, &mut ast);
+                    metrics.elided_values = values.saturating_sub(count_values(&ast));
+                    Analysis {
+                        fingerprint,
+                        kind: classifications
+                            .first()
+                            .map_or(StatementKind::Unknown, |(kind, _)| *kind),
+                        read_only: !classifications.is_empty()
+                            && classifications.iter().all(|(_, read_only)| *read_only),
+                        tables,
+                        columns,
+                        metrics,
+                        lineage,
+                    }
+                }
+                Err(_) => {
+                    let (kind, read_only) = Tokenizer::new(self.dialect, sql)
+                        .tokenize()
+                        .map_or((StatementKind::Unknown, false), |tokens| {
+                            classify_tokens(&tokens)
+                        });
+                    Analysis {
+                        fingerprint: sql.to_string(),
+                        kind,
+                        read_only,
+                        tables: vec![],
+                        columns: vec![],
+                        metrics: QueryMetrics::default(),
+                        lineage: vec![],
+                    }
+                }
+            })
+            .collect()
+    }
+
+    /// Group a batch of SQL strings by fingerprint, counting occurrences.
+    ///
+    /// # Example
+    /// ```
+    /// use sql_fingerprint::{Fingerprinter, GroupOrder};
+    ///
+    /// let groups = Fingerprinter::new().group_many(
+    ///     vec![
+    ///         "SELECT * FROM authors",
+    ///         "SELECT * FROM books WHERE author_id = 1",
+    ///         "SELECT * FROM books WHERE author_id = 2",
+    ///     ],
+    ///     GroupOrder::Count,
+    /// );
+    /// assert_eq!(groups[0].fingerprint, "SELECT * FROM books WHERE ...");
+    /// assert_eq!(groups[0].count, 2);
+    /// assert_eq!((groups[0].first, groups[0].last), (1, 2));
+    /// assert_eq!(groups[0].example, "SELECT * FROM books WHERE author_id = 1");
+    /// ```
+    pub fn group_many(&self, input: Vec<&str>, order: GroupOrder) -> Vec<FingerprintGroup> {
+        let fingerprints = self.fingerprint_many(input.clone());
+        group_fingerprints(&input, fingerprints, None, order)
+    }
+
+    /// Group a batch of SQL strings by fingerprint, like [`Fingerprinter::group_many`], also
+    /// recording each query's duration.
+    pub fn group_many_with_durations(
+        &self,
+        input: Vec<(&str, Duration)>,
+        order: GroupOrder,
+    ) -> Vec<FingerprintGroup> {
+        let (queries, durations): (Vec<&str>, Vec<Duration>) = input.into_iter().unzip();
+        let fingerprints = self.fingerprint_many(queries.clone());
+        group_fingerprints(&queries, fingerprints, Some(&durations), order)
+    }
+
+    /// Analyze the structure of transactions and savepoints in a batch of SQL strings, such as
+    /// a trace of the queries run by a test.
+    ///
+    /// Savepoint names are aliased like in fingerprints, to 's1', 's2', etc.
+    ///
+    /// # Example
+    /// ```
+    /// use sql_fingerprint::Fingerprinter;
+    ///
+    /// let report = Fingerprinter::new().analyze_transactions(vec![
+    ///     "SELECT * FROM users",
+    ///     "BEGIN",
+    ///     "SAVEPOINT a",
+    ///     "UPDATE users SET name = 'Alice'",
+    ///     "COMMIT",
+    /// ]);
+    /// assert_eq!(report.outside_transaction, vec![0]);
+    /// assert_eq!(report.unreleased_savepoints, vec![2]);
+    /// assert_eq!(report.max_depth, 2);
+    /// ```
+    pub fn analyze_transactions(&self, input: Vec<&str>) -> TransactionReport {
+        let mut visitor = FingerprintingVisitor::new(self);
+        let mut tracker = TransactionTracker::default();
+
+        for (index, sql) in input.iter().enumerate() {
+            match parse_sql(self.dialect, sql) {
+                Ok(mut ast) => {
+                    for stmt in &mut ast {
+                        // Visit to alias savepoint names.
+                        let _ = stmt.visit(&mut visitor);
+                        tracker.statement(index, stmt);
+                    }
+                }
+                Err(_) => {
+                    let kind = Tokenizer::new(self.dialect, sql)
+                        .tokenize()
+                        .map_or(StatementKind::Unknown, |tokens| classify_tokens(&tokens).0);
+                    if !matches!(
+                        kind,
+                        StatementKind::TransactionControl | StatementKind::Savepoint
+                    ) {
+                        tracker.query(index);
+                    }
                 }
             }
         }
+        tracker.finish()
+    }
 
-        if let Some(selection) = &mut select.selection {
-            *selection = placeholder_value();
+    /// Fingerprint parsed statements, returning the fingerprint and, if the visitor is capturing
+    /// them, the elided values in output order.
+    fn fingerprint_statements(
+        &self,
+        visitor: &mut FingerprintingVisitor,
+        ast: &mut [Statement],
+    ) -> (String, Vec<String>) {
+        if let Some(elided) = &mut visitor.elided {
+            elided.clear();
         }
-
-        if let GroupByExpr::Expressions(col_names, ..) = &mut select.group_by
-            && !col_names.is_empty()
-        {
-            *col_names = vec![placeholder_value()];
+        if self.anonymize_aliases {
+            visitor.aliases = anonymize_alias_definitions(ast);
+        }
+        for stmt in ast.iter_mut() {
+            let _ = stmt.visit(visitor);
+        }
+        // Sorting may have moved alias definitions, so number them again in their new order.
+        if self.anonymize_aliases && self.sort_commutative_operands {
+            renumber_aliases(ast);
+        }
+        // Number placeholders last, so they are in order after any reordering.
+        if let Some(style) = self.placeholder_style {
+            let mut numbering = PlaceholderNumbering {
+                style,
+                numbers: HashMap::new(),
+                count: 0,
+            };
+            for stmt in ast.iter_mut() {
+                let _ = stmt.visit(&mut numbering);
+            }
+        }
+        let render = |ast: &[Statement]| {
+            ast.iter()
+                .map(|stmt| stmt.to_string())
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+        let fingerprint = render(ast);
+        let Some(elided) = &visitor.elided else {
+            return (fingerprint, vec![]);
+        };
+        // Render elisions as markers to find their output order, since placeholders may have
+        // been reordered or dropped since they were recorded. The marker is a private use
+        // character that the fingerprint doesn't contain, so input text can't forge one.
+        let Some(marker) = ('\u{E000}'..='\u{F8FF}').find(|&c| !fingerprint.contains(c)) else {
+            return (fingerprint, vec![]);
+        };
+        for stmt in ast.iter_mut() {
+            let _ = stmt.visit(&mut ElisionMarking { marker });
         }
+        let values = render(ast)
+            .split(marker)
+            .skip(1)
+            .step_by(2)
+            .filter_map(|id| elided.get(id.parse::<usize>().ok()?.checked_sub(1)?))
+            .cloned()
+            .collect();
+        (fingerprint, values)
     }
-}
 
-impl VisitorMut for FingerprintingVisitor {
-    type Break = ();
+    /// Collect the tables referenced by each statement.
+    fn collect_tables(&self, ast: &[Statement]) -> Vec<Vec<TableReference>> {
+        let mut collector = TableCollector {
+            options: self,
+            cte_scopes: vec![],
+            pending_targets: vec![],
+            in_table_function: false,
+            tables: vec![],
+        };
+        ast.iter()
+            .map(|stmt| {
+                collector.cte_scopes.clear();
+                let _ = stmt.visit(&mut collector);
+                std::mem::take(&mut collector.tables)
+            })
+            .collect()
+    }
 
-    fn pre_visit_statement(&mut self, stmt: &mut Statement) -> ControlFlow<Self::Break> {
-        match stmt {
-            Statement::Savepoint { name } => {
-                let savepoint_id = format!("s{}", self.savepoint_ids.len() + 1);
-                self.savepoint_ids
-                    .insert(name.value.clone(), savepoint_id.clone());
-                *name = Ident::new(savepoint_id);
-            }
-            Statement::ReleaseSavepoint { name } => {
-                if let Some(savepoint_id) = self.savepoint_ids.get(&name.value).cloned() {
-                    *name = Ident::new(savepoint_id);
-                }
-            }
-            Statement::Rollback {
-                savepoint: Some(name),
-                ..
-            } => {
-                if let Some(savepoint_id) = self.savepoint_ids.get(&name.value).cloned() {
-                    *name = Ident::new(savepoint_id);
+    fn collect_columns(&self, ast: &[Statement]) -> Vec<ColumnReference> {
+        let mut collector = ColumnCollector {
+            options: self,
+            cte_scopes: vec![],
+            scopes: vec![],
+            columns: vec![],
+        };
+        for stmt in ast {
+            collector.cte_scopes.clear();
+            collector.collect_statement(stmt);
+        }
+        collector.columns
+    }
+
+    /// Collect lineage edges, given the tables referenced by each statement.
+    fn collect_lineage(
+        &self,
+        ast: &[Statement],
+        statement_tables: &[Vec<TableReference>],
+    ) -> Vec<LineageEdge> {
+        let mut edges = vec![];
+        for (statement, tables) in iter::zip(ast, statement_tables) {
+            let tables_with_role = |role| -> Vec<LineageNode> {
+                tables
+                    .iter()
+                    .filter(|table| table.role == role)
+                    .map(|table| LineageNode::Table(table.name.clone()))
+                    .collect()
+            };
+            let (sources, targets) = match data_statement(statement) {
+                Statement::Insert(_) | Statement::Merge(_) => (
+                    tables_with_role(TableRole::Read),
+                    tables_with_role(TableRole::Write),
+                ),
+                Statement::CreateTable(CreateTable { query: Some(_), .. }) => (
+                    tables_with_role(TableRole::Read),
+                    tables_with_role(TableRole::DdlTarget),
+                ),
+                Statement::Copy {
+                    source, to, target, ..
+                } => {
+                    let file = match target {
+                        CopyTarget::Stdin => LineageNode::Stdin,
+                        CopyTarget::Stdout => LineageNode::Stdout,
+                        CopyTarget::File { filename } => LineageNode::File(filename.clone()),
+                        CopyTarget::Program { command } => LineageNode::Program(command.clone()),
+                    };
+                    match (source, to) {
+                        (_, true) => (tables_with_role(TableRole::Read), vec![file]),
+                        (CopySource::Table { .. }, false) => {
+                            (vec![file], tables_with_role(TableRole::Write))
+                        }
+                        (CopySource::Query(_), false) => continue,
+                    }
                 }
-            }
-            Statement::Declare { stmts } => {
-                for stmt in stmts {
-                    if !stmt.names.is_empty() {
-                        stmt.names = vec![Ident::new("...")];
+                _ => continue,
+            };
+            let columns = self.collect_column_lineage(statement);
+            for target in &targets {
+                for source in &sources {
+                    let mut edge = LineageEdge {
+                        source: source.clone(),
+                        target: target.clone(),
+                        columns: vec![],
+                    };
+                    for (table, column) in &columns {
+                        if matches!(source, LineageNode::Table(name) if name == table)
+                            && !edge.columns.contains(column)
+                        {
+                            edge.columns.push(column.clone());
+                        }
                     }
+                    edges.push(edge);
                 }
             }
+        }
+        edges
+    }
+
+    /// Collect the columns copied directly into a statement's target, with their source tables.
+    fn collect_column_lineage(&self, statement: &Statement) -> Vec<(String, ColumnLineage)> {
+        let mut collector = ColumnCollector {
+            options: self,
+            cte_scopes: vec![],
+            scopes: vec![],
+            columns: vec![],
+        };
+        if let Statement::Query(query) = statement {
+            collector.cte_scopes.push(cte_names(query));
+        }
+        match data_statement(statement) {
             Statement::Insert(Insert {
                 columns,
-                source,
-                on,
-                returning,
+                source: Some(source),
                 ..
-            }) => {
-                if !columns.is_empty() {
-                    *columns = vec![Ident::new("...").into()];
-                }
-                if let Some(source) = source
-                    && let SetExpr::Values(values) = source.as_mut().body.as_mut()
-                {
-                    values.rows = vec![Parens::with_empty_span(vec![placeholder_value()])];
-                }
-                if let Some(OnInsert::OnConflict(OnConflict {
-                    conflict_target,
-                    action,
-                })) = on
-                {
-                    if let Some(ConflictTarget::Columns(columns)) = conflict_target
-                        && !columns.is_empty()
-                    {
-                        *columns = vec![Ident::new("...")];
-                    }
-                    if let OnConflictAction::DoUpdate(DoUpdate {
-                        assignments,
-                        selection,
-                    }) = action
-                    {
-                        if !assignments.is_empty() {
-                            *assignments = vec![Assignment {
-                                target: AssignmentTarget::ColumnName(ObjectName(vec![
-                                    ObjectNamePart::Identifier(Ident::new("...")),
-                                ])),
-                                value: placeholder_value(),
-                            }];
-                        }
-                        if let Some(selection) = selection {
-                            *selection = placeholder_value();
-                        }
-                    }
-                }
-                if let Some(returning) = returning
-                    && !returning.is_empty()
-                {
-                    *returning = vec![SelectItem::UnnamedExpr(placeholder_value())];
-                }
+            }) if !columns.is_empty() => {
+                let targets = columns
+                    .iter()
+                    .map(|column| column.0.last().and_then(|part| part.as_ident()))
+                    .map(|ident| ident.map(|ident| collector.column_name(ident)))
+                    .collect();
+                collector.collect_projection_lineage(source, Some(targets))
             }
-            Statement::Update(Update {
-                assignments,
-                selection,
-                returning,
+            Statement::CreateTable(CreateTable {
+                columns,
+                query: Some(query),
                 ..
             }) => {
-                if !assignments.is_empty() {
-                    *assignments = vec![sqlparser::ast::Assignment {
-                        target: AssignmentTarget::ColumnName(ObjectName(vec![
-                            ObjectNamePart::Identifier(Ident::new("...")),
-                        ])),
-                        value: placeholder_value(),
-                    }];
-                }
-                if let Some(selection) = selection {
-                    *selection = placeholder_value();
-                }
-                if let Some(returning) = returning
-                    && !returning.is_empty()
-                {
-                    *returning = vec![SelectItem::UnnamedExpr(placeholder_value())];
-                }
+                let targets = (!columns.is_empty()).then(|| {
+                    columns
+                        .iter()
+                        .map(|column| Some(collector.column_name(&column.name)))
+                        .collect()
+                });
+                collector.collect_projection_lineage(query, targets)
             }
-            Statement::Delete(Delete {
-                selection,
-                returning,
+            Statement::Merge(Merge {
+                table,
+                source,
+                clauses,
                 ..
             }) => {
-                if let Some(selection) = selection {
-                    *selection = placeholder_value();
-                }
-                if let Some(returning) = returning
-                    && !returning.is_empty()
-                {
-                    *returning = vec![SelectItem::UnnamedExpr(placeholder_value())];
-                }
-            }
-            _ => {}
-        }
-        ControlFlow::Continue(())
-    }
-
-    fn pre_visit_query(&mut self, query: &mut Query) -> ControlFlow<Self::Break> {
-        match query.body.as_mut() {
-            SetExpr::Select(select) => {
-                self.visit_select(select);
-            }
-            SetExpr::SetOperation { left, right, .. } => {
-                // push left and right into a double-ended queue to visit them,
-                // expnading left and right as required.
-                let mut stack = vec![left.as_mut(), right.as_mut()];
-                while let Some(set_expr) = stack.pop() {
-                    match set_expr {
-                        SetExpr::Select(select) => {
-                            self.visit_select(select);
+                let mut scope = ColumnScope::default();
+                collector.add_scope_relation(&mut scope, table);
+                collector.add_scope_relation(&mut scope, source);
+                collector.scopes.push(scope);
+                let mut lineage = vec![];
+                for clause in clauses {
+                    let MergeAction::Insert(MergeInsertExpr {
+                        columns,
+                        kind: MergeInsertKind::Values(values),
+                        ..
+                    }) = &clause.action
+                    else {
+                        continue;
+                    };
+                    for row in &values.rows {
+                        if row.content.len() != columns.len() {
+                            continue;
                         }
-                        SetExpr::SetOperation { left, right, .. } => {
-                            // Push left and right onto the stack for further processing.
-                            stack.push(left.as_mut());
-                            stack.push(right.as_mut());
+                        for (column, expr) in columns.iter().zip(&row.content) {
+                            if let Some(ident) = column.0.last().and_then(|part| part.as_ident()) {
+                                let target = collector.column_name(ident);
+                                lineage.extend(collector.column_lineage(expr, target));
+                            }
                         }
-                        _ => {}
                     }
                 }
+                lineage
             }
-            _ => {}
+            _ => vec![],
         }
-        if let Some(order_by) = &mut query.order_by {
-            let OrderBy { kind, .. } = order_by;
-            if let OrderByKind::Expressions(expressions) = kind
-                && !expressions.is_empty()
-            {
-                if let Some(expr) = expressions.first_mut() {
-                    expr.expr = placeholder_value();
-                }
-                expressions.truncate(1);
-            }
+    }
+
+    /// Apply schema qualification, unquoting, and rewrite rules to a relation name.
+    fn normalize_relation_name(&self, name: &mut ObjectName) {
+        if let Some(qualification) = &self.schema_qualification {
+            qualification.apply_to_relation(name);
         }
-        if let Some(limit_clause) = &mut query.limit_clause {
-            match limit_clause {
-                LimitClause::LimitOffset {
-                    limit,
-                    offset,
-                    limit_by,
-                } => {
-                    if let Some(limit_value) = limit {
-                        *limit_value = placeholder_value();
-                    }
-                    if let Some(Offset { value, .. }) = offset {
-                        *value = placeholder_value();
-                    }
-                    if !limit_by.is_empty() {
-                        *limit_by = vec![placeholder_value()];
-                    }
-                }
-                // MySQL specific, needs testing!Ó
-                LimitClause::OffsetCommaLimit { offset, limit } => {
-                    *offset = placeholder_value();
-                    *limit = placeholder_value();
-                }
+        self.maybe_unquote_object_name(name);
+        for part in name.0.iter_mut() {
+            if let ObjectNamePart::Identifier(ident) = part {
+                self.rewrite_relation_name_part(ident);
             }
         }
-        ControlFlow::Continue(())
     }
 
-    fn pre_visit_relation(&mut self, _relation: &mut ObjectName) -> ControlFlow<Self::Break> {
-        for part in _relation.0.iter_mut() {
+    /// Apply the rewrite rules to a part of a relation name, or of a column's qualifier.
+    fn rewrite_relation_name_part(&self, ident: &mut Ident) {
+        if let Some(value) = self
+            .relation_name_rules
+            .iter()
+            .find_map(|rule| rule(&ident.value))
+        {
+            ident.value = value;
+        }
+    }
+
+    fn resolved_identifier_case(&self) -> IdentifierCase {
+        self.identifier_case
+            .unwrap_or_else(|| IdentifierCase::for_dialect(self.dialect))
+    }
+
+    fn maybe_unquote_object_name(&self, name: &mut ObjectName) {
+        for part in name.0.iter_mut() {
             if let ObjectNamePart::Identifier(ident) = part {
-                maybe_unquote_ident(ident);
+                self.maybe_unquote_ident(ident);
             }
         }
-        ControlFlow::Continue(())
     }
 
-    fn pre_visit_table_factor(
-        &mut self,
-        table_factor: &mut TableFactor,
-    ) -> ControlFlow<Self::Break> {
-        if let TableFactor::UNNEST {
-            alias, array_exprs, ..
-        } = table_factor
+    fn maybe_unquote_ident(&self, ident: &mut Ident) {
+        let Ident {
+            value, quote_style, ..
+        } = ident;
+        let case = self.resolved_identifier_case();
+
+        if quote_style.is_some()
+            && value.chars().all(|c| {
+                c == '_'
+                    || c.is_ascii_alphanumeric()
+                    || (self.unquote_non_ascii_identifiers && c.is_alphanumeric())
+            })
+            && value
+                .chars()
+                .next()
+                .is_some_and(|c| self.dialect.is_identifier_start(c))
+            && case.preserves_unquoted(value)
+            && !self.is_reserved_keyword(value)
+        {
+            *quote_style = None;
+        }
+
+        if self.lowercase_identifiers && quote_style.is_none() && case != IdentifierCase::Sensitive
+        {
+            *value = value.to_lowercase();
+        }
+    }
+
+    /// Check if the given name is a keyword that needs quoting to be used as an identifier.
+    fn is_reserved_keyword(&self, name: &str) -> bool {
+        let Ok(index) = ALL_KEYWORDS.binary_search(&name.to_ascii_uppercase().as_str()) else {
+            return false;
+        };
+        let keyword = ALL_KEYWORDS_INDEX[index];
+        self.dialect.is_reserved_for_identifier(keyword)
+            || RESERVED_KEYWORDS.contains(&keyword)
+            || dialect_reserved_keywords(self.dialect).contains(&keyword)
+    }
+}
+
+/// Keywords reserved by a specific dialect on top of [`RESERVED_KEYWORDS`].
+fn dialect_reserved_keywords(dialect: &dyn Dialect) -> &'static [Keyword] {
+    if dialect.is::<MySqlDialect>() {
+        &[
+            Keyword::DIV,
+            Keyword::INDEX,
+            Keyword::KEY,
+            Keyword::KEYS,
+            Keyword::MOD,
+            Keyword::RANGE,
+            Keyword::READ,
+            Keyword::REGEXP,
+            Keyword::RLIKE,
+            Keyword::WRITE,
+            Keyword::XOR,
+        ]
+    } else if dialect.is::<MsSqlDialect>() {
+        &[
+            Keyword::CLUSTERED,
+            Keyword::IDENTITY,
+            Keyword::TOP,
+            Keyword::TRAN,
+        ]
+    } else {
+        &[]
+    }
+}
+
+/// Keywords reserved in the SQL standard and common databases, which sqlparser's own lists
+/// don't cover, since it can parse some of them as identifiers.
+const RESERVED_KEYWORDS: &[Keyword] = &[
+    Keyword::ALL,
+    Keyword::ANALYZE,
+    Keyword::AND,
+    Keyword::ANY,
+    Keyword::ARRAY,
+    Keyword::AS,
+    Keyword::ASC,
+    Keyword::ASYMMETRIC,
+    Keyword::BETWEEN,
+    Keyword::BOTH,
+    Keyword::CASE,
+    Keyword::CAST,
+    Keyword::CHECK,
+    Keyword::COLLATE,
+    Keyword::COLUMN,
+    Keyword::CONSTRAINT,
+    Keyword::CREATE,
+    Keyword::CROSS,
+    Keyword::CURRENT_CATALOG,
+    Keyword::CURRENT_DATE,
+    Keyword::CURRENT_ROLE,
+    Keyword::CURRENT_TIME,
+    Keyword::CURRENT_TIMESTAMP,
+    Keyword::CURRENT_USER,
+    Keyword::DEFAULT,
+    Keyword::DEFERRABLE,
+    Keyword::DESC,
+    Keyword::DISTINCT,
+    Keyword::DO,
+    Keyword::ELSE,
+    Keyword::END,
+    Keyword::EXCEPT,
+    Keyword::FALSE,
+    Keyword::FETCH,
+    Keyword::FOR,
+    Keyword::FOREIGN,
+    Keyword::FROM,
+    Keyword::FULL,
+    Keyword::GRANT,
+    Keyword::GROUP,
+    Keyword::HAVING,
+    Keyword::ILIKE,
+    Keyword::IN,
+    Keyword::INITIALLY,
+    Keyword::INNER,
+    Keyword::INTERSECT,
+    Keyword::INTO,
+    Keyword::IS,
+    Keyword::JOIN,
+    Keyword::LATERAL,
+    Keyword::LEADING,
+    Keyword::LEFT,
+    Keyword::LIKE,
+    Keyword::LIMIT,
+    Keyword::LOCALTIME,
+    Keyword::LOCALTIMESTAMP,
+    Keyword::NATURAL,
+    Keyword::NOT,
+    Keyword::NULL,
+    Keyword::OFFSET,
+    Keyword::ON,
+    Keyword::ONLY,
+    Keyword::OR,
+    Keyword::ORDER,
+    Keyword::OUTER,
+    Keyword::PLACING,
+    Keyword::PRIMARY,
+    Keyword::REFERENCES,
+    Keyword::RETURNING,
+    Keyword::RIGHT,
+    Keyword::SELECT,
+    Keyword::SESSION_USER,
+    Keyword::SOME,
+    Keyword::SYMMETRIC,
+    Keyword::SYSTEM_USER,
+    Keyword::TABLE,
+    Keyword::THEN,
+    Keyword::TO,
+    Keyword::TRAILING,
+    Keyword::TRUE,
+    Keyword::UNION,
+    Keyword::UNIQUE,
+    Keyword::USER,
+    Keyword::USING,
+    Keyword::VARIADIC,
+    Keyword::WHEN,
+    Keyword::WHERE,
+    Keyword::WINDOW,
+    Keyword::WITH,
+];
+
+/// The style that bind parameters are rewritten to by [`Fingerprinter::placeholder_style`].
+///
+/// Numbered styles count up from 1 within each input string, with reused numbered or named
+/// parameters keeping the same number.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PlaceholderStyle {
+    /// `?`, as used by SQLite, MySQL, and JDBC.
+    QuestionMark,
+    /// `$1`, `$2`, etc., as used by PostgreSQL.
+    DollarNumbered,
+    /// `:1`, `:2`, etc., as used by Oracle.
+    ColonNumbered,
+    /// `%s`, as used by Python's format and pyformat parameter styles.
+    Format,
+}
+
+impl PlaceholderStyle {
+    fn placeholder(self, number: usize) -> String {
+        match self {
+            PlaceholderStyle::QuestionMark => "?".to_string(),
+            PlaceholderStyle::DollarNumbered => format!("${number}"),
+            PlaceholderStyle::ColonNumbered => format!(":{number}"),
+            PlaceholderStyle::Format => "%s".to_string(),
+        }
+    }
+}
+
+/// How a database treats the case of unquoted identifiers, as set by
+/// [`Fingerprinter::identifier_case`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IdentifierCase {
+    /// Unquoted identifiers are folded to lowercase, as in PostgreSQL.
+    FoldLower,
+    /// Unquoted identifiers are folded to uppercase, as in the SQL standard, Oracle, and
+    /// Snowflake.
+    FoldUpper,
+    /// Unquoted identifiers keep their case and match case-insensitively, as in MySQL, SQLite,
+    /// and SQL Server.
+    Insensitive,
+    /// Unquoted identifiers keep their case and match case-sensitively, as in ClickHouse.
+    Sensitive,
+}
+
+impl IdentifierCase {
+    fn for_dialect(dialect: &dyn Dialect) -> Self {
+        if dialect.is::<PostgreSqlDialect>() || dialect.is::<RedshiftSqlDialect>() {
+            IdentifierCase::FoldLower
+        } else if dialect.is::<AnsiDialect>()
+            || dialect.is::<OracleDialect>()
+            || dialect.is::<SnowflakeDialect>()
+        {
+            IdentifierCase::FoldUpper
+        } else if dialect.is::<ClickHouseDialect>() {
+            IdentifierCase::Sensitive
+        } else {
+            IdentifierCase::Insensitive
+        }
+    }
+
+    /// Check if the given name would be unchanged by folding, if unquoted.
+    fn preserves_unquoted(self, name: &str) -> bool {
+        match self {
+            IdentifierCase::FoldLower => !name.chars().any(char::is_uppercase),
+            IdentifierCase::FoldUpper => !name.chars().any(char::is_lowercase),
+            IdentifierCase::Insensitive | IdentifierCase::Sensitive => true,
+        }
+    }
+}
+
+/// How [`Fingerprinter::schema_qualification`] normalizes schema and catalog qualifiers.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SchemaQualification {
+    /// Remove the qualifiers from names in the given default schema, such as `public`.
+    /// Any catalog qualifier is removed along with the schema.
+    StripDefault(String),
+    /// Remove all schema and catalog qualifiers.
+    StripAll,
+    /// Qualify unqualified relation names with the given default schema, and remove catalog
+    /// qualifiers.
+    QualifyWith(String),
+}
+
+impl SchemaQualification {
+    /// Return how many leading qualifiers to remove, given the qualifiers of a name.
+    fn strip_count(&self, qualifiers: &[Option<&Ident>]) -> usize {
+        match self {
+            SchemaQualification::StripDefault(schema) => match qualifiers.last() {
+                Some(Some(ident)) if ident_matches(ident, schema) => qualifiers.len(),
+                _ => 0,
+            },
+            SchemaQualification::StripAll => qualifiers.len(),
+            SchemaQualification::QualifyWith(_) => qualifiers.len().saturating_sub(1),
+        }
+    }
+
+    fn apply_to_relation(&self, name: &mut ObjectName) {
+        let parts = &mut name.0;
+        if parts.is_empty() {
+            return;
+        }
+        let qualifiers: Vec<_> = parts[..parts.len() - 1]
+            .iter()
+            .map(ObjectNamePart::as_ident)
+            .collect();
+        let strip_count = self.strip_count(&qualifiers);
+        parts.drain(..strip_count);
+        if let SchemaQualification::QualifyWith(schema) = self
+            && parts.len() == 1
+        {
+            parts.insert(0, ObjectNamePart::Identifier(Ident::new(schema)));
+        }
+    }
+
+    fn apply_to_compound_identifier(&self, idents: &mut Vec<Ident>) {
+        // Only names with a column, table, and further qualifiers are affected.
+        if idents.len() < 3 {
+            return;
+        }
+        let qualifiers: Vec<_> = idents[..idents.len() - 2].iter().map(Some).collect();
+        let strip_count = self.strip_count(&qualifiers);
+        idents.drain(..strip_count);
+    }
+}
+
+/// The result of [`Fingerprinter::analyze_one`] and [`Fingerprinter::analyze_many`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Analysis {
+    /// The fingerprint, as returned by [`Fingerprinter::fingerprint_one`].
+    pub fingerprint: String,
+    /// The kind of the first statement.
+    pub kind: StatementKind,
+    /// Whether all statements only read data, so could run on a read replica.
+    /// Unknown statements are assumed to write.
+    pub read_only: bool,
+    /// The tables referenced, in order of first appearance.
+    pub tables: Vec<TableReference>,
+    /// The columns referenced, in order of first appearance.
+    pub columns: Vec<ColumnReference>,
+    /// Complexity metrics, summed over all statements.
+    pub metrics: QueryMetrics,
+    /// The flows of data from sources to targets, for `INSERT ... SELECT`, `CREATE TABLE ... AS`,
+    /// `MERGE`, and `COPY` statements.
+    pub lineage: Vec<LineageEdge>,
+}
+
+/// A table referenced by a statement, with its name normalized like in the fingerprint.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct TableReference {
+    /// The table name, with any qualifiers.
+    pub name: String,
+    /// How the statement uses the table.
+    pub role: TableRole,
+}
+
+impl TableReference {
+    /// Create a table reference.
+    pub fn new(name: impl Into<String>, role: TableRole) -> Self {
+        TableReference {
+            name: name.into(),
+            role,
+        }
+    }
+}
+
+/// How a statement uses a [`TableReference`].
+/// A table used in several ways is listed once per role.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum TableRole {
+    /// Rows are read, as in `FROM` and `JOIN` clauses, and `COPY ... TO`.
+    Read,
+    /// Rows are written, as in `INSERT`, `UPDATE`, `DELETE`, `MERGE`, `TRUNCATE`, and
+    /// `COPY ... FROM`.
+    Write,
+    /// The table itself is created, altered, renamed, or dropped.
+    DdlTarget,
+    /// The table is locked, with `LOCK TABLE` or `SELECT ... FOR UPDATE` and similar.
+    LockTarget,
+}
+
+/// Complexity metrics for an [`Analysis`], such as for alerting on ORM changes that make
+/// queries explode in size.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct QueryMetrics {
+    /// The number of joins, including comma-separated tables in `FROM`.
+    pub joins: usize,
+    /// The number of subqueries, in expressions or `FROM` clauses, not counting CTEs.
+    pub subqueries: usize,
+    /// The maximum depth of nested queries, including CTEs, or 0 if there are none.
+    pub max_nesting_depth: usize,
+    /// The number of common table expressions (CTEs).
+    pub ctes: usize,
+    /// The number of set operations, like `UNION`.
+    pub set_operations: usize,
+    /// The number of predicates, such as comparisons, `IN`, `LIKE`, `IS NULL`, and `EXISTS`.
+    pub predicates: usize,
+    /// The number of items in the outermost `SELECT` list.
+    pub projection_width: usize,
+    /// The number of values, including bind parameters, that the fingerprint elides.
+    pub elided_values: usize,
+}
+
+/// The result of [`Fingerprinter::analyze_transactions`].
+/// Positions are indexes into the input batch.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct TransactionReport {
+    /// The transactions, in order.
+    pub transactions: Vec<Transaction>,
+    /// The maximum nesting depth, counting 1 for a transaction and 1 for each savepoint level.
+    pub max_depth: usize,
+    /// The positions of savepoints that were still active when their transaction ended, or at
+    /// the end of the batch.
+    pub unreleased_savepoints: Vec<usize>,
+    /// The positions of `ROLLBACK TO SAVEPOINT` and `RELEASE SAVEPOINT` statements that name a
+    /// savepoint that isn't active.
+    pub unknown_savepoints: Vec<usize>,
+    /// The positions of statements run outside any transaction, other than transaction control.
+    pub outside_transaction: Vec<usize>,
+    /// The positions of transaction control statements that don't fit the transaction state:
+    /// `BEGIN` within a transaction, and `COMMIT`, `ROLLBACK`, or `SAVEPOINT` outside one.
+    pub unexpected_transaction_control: Vec<usize>,
+}
+
+/// A transaction in a [`TransactionReport`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Transaction {
+    /// The position of the statement that started the transaction.
+    pub start: usize,
+    /// The position of the `COMMIT` or `ROLLBACK` that ended the transaction, if it ended.
+    pub end: Option<usize>,
+    /// Whether the transaction ended with `ROLLBACK`.
+    pub rolled_back: bool,
+    /// The top-level savepoints in the transaction.
+    pub savepoints: Vec<Savepoint>,
+}
+
+/// A savepoint in a [`Transaction`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Savepoint {
+    /// The savepoint name, aliased like in fingerprints.
+    pub name: String,
+    /// The position of the `SAVEPOINT` statement.
+    pub start: usize,
+    /// The position of the `RELEASE SAVEPOINT` statement that released this savepoint, or an
+    /// enclosing one.
+    pub released: Option<usize>,
+    /// The positions of `ROLLBACK TO SAVEPOINT` statements for this savepoint.
+    pub rollbacks: Vec<usize>,
+    /// The savepoints created within this savepoint.
+    pub savepoints: Vec<Savepoint>,
+}
+
+/// A column referenced by a statement, with names normalized like in the fingerprint.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ColumnReference {
+    /// The table the column belongs to, if known.
+    /// Qualifiers that are aliases are resolved to their table, and unqualified columns are
+    /// resolved when their query has a single table.
+    pub table: Option<String>,
+    /// The column name, or `*` for wildcards.
+    pub name: String,
+    /// The clause the column is referenced in.
+    pub clause: ColumnClause,
+}
+
+impl ColumnReference {
+    /// Create a column reference.
+    pub fn new(table: Option<&str>, name: impl Into<String>, clause: ColumnClause) -> Self {
+        ColumnReference {
+            table: table.map(str::to_string),
+            name: name.into(),
+            clause,
+        }
+    }
+}
+
+/// The clause a [`ColumnReference`] is found in.
+/// Columns in subqueries are reported with the clause of their own query.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ColumnClause {
+    /// The `SELECT` list.
+    Projection,
+    /// `WHERE` clauses, including in `UPDATE` and `DELETE` statements.
+    Where,
+    /// `JOIN` conditions, with `ON` or `USING`.
+    Join,
+    /// `GROUP BY` clauses.
+    GroupBy,
+    /// `HAVING` clauses.
+    Having,
+    /// `ORDER BY` clauses.
+    OrderBy,
+}
+
+/// A flow of data from a source to a target, as reported in an [`Analysis`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct LineageEdge {
+    /// Where the data comes from.
+    pub source: LineageNode,
+    /// Where the data goes.
+    pub target: LineageNode,
+    /// The columns copied directly from the source to the target, where the source's
+    /// projection or values line up with the target's columns.
+    pub columns: Vec<ColumnLineage>,
+}
+
+/// An end of a [`LineageEdge`].
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum LineageNode {
+    /// A table, with its name normalized like in the fingerprint.
+    Table(String),
+    /// A file read or written by `COPY`.
+    File(String),
+    /// A program run by `COPY`.
+    Program(String),
+    /// Standard input, read by `COPY ... FROM STDIN`.
+    Stdin,
+    /// Standard output, written by `COPY ... TO STDOUT`.
+    Stdout,
+}
+
+/// A column copied from a source to a target column, in a [`LineageEdge`].
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ColumnLineage {
+    /// The source column.
+    pub source: String,
+    /// The target column.
+    pub target: String,
+}
+
+impl ColumnLineage {
+    /// Create a column lineage.
+    pub fn new(source: impl Into<String>, target: impl Into<String>) -> Self {
+        ColumnLineage {
+            source: source.into(),
+            target: target.into(),
+        }
+    }
+}
+
+/// The kind of a statement, as reported in an [`Analysis`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum StatementKind {
+    /// `SELECT`, `VALUES`, and other queries.
+    Select,
+    /// `INSERT`, including `INSERT ... ON CONFLICT DO NOTHING` and `INSERT OR IGNORE`.
+    Insert,
+    /// `UPDATE`.
+    Update,
+    /// `DELETE`.
+    Delete,
+    /// `MERGE`.
+    Merge,
+    /// `INSERT` that may update existing rows, with `ON CONFLICT DO UPDATE`,
+    /// `ON DUPLICATE KEY UPDATE`, `REPLACE INTO`, or `INSERT OR REPLACE`.
+    Upsert,
+    /// `CREATE`, `ALTER`, `DROP`, `TRUNCATE`, `RENAME`, and `COMMENT` statements.
+    Ddl,
+    /// `BEGIN`, `COMMIT`, `ROLLBACK`, and `SET TRANSACTION`.
+    TransactionControl,
+    /// `SAVEPOINT`, `RELEASE SAVEPOINT`, and `ROLLBACK TO SAVEPOINT`.
+    Savepoint,
+    /// `SET`, `RESET`, `USE`, and `DISCARD`.
+    Session,
+    /// Other recognized statements, such as `EXPLAIN`, `SHOW`, `COPY`, `LOCK TABLE`, and
+    /// `GRANT`.
+    Utility,
+    /// Unrecognized statements.
+    Unknown,
+}
+
+/// A fingerprint with the original text of each elided `...`, from
+/// [`Fingerprinter::fingerprint_many_with_values`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct FingerprintWithValues {
+    /// The fingerprint.
+    pub fingerprint: String,
+    /// The literal values or expression text that each `...` replaced, in output order.
+    ///
+    /// Each value is the text to substitute for its `...`, so surrounding syntax stays in the
+    /// fingerprint: `VALUES (1, 2), (3, 4)` becomes `VALUES (...)` with the value
+    /// `1, 2), (3, 4`.
+    pub values: Vec<String>,
+}
+
+/// How [`Fingerprinter::group_many`] orders its groups.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GroupOrder {
+    /// Most frequent first, breaking ties by first appearance.
+    Count,
+    /// In order of first appearance.
+    FirstAppearance,
+}
+
+/// A group of queries sharing a fingerprint, from [`Fingerprinter::group_many`].
+/// Positions are indexes into the input batch.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct FingerprintGroup {
+    /// The shared fingerprint.
+    pub fingerprint: String,
+    /// The number of queries.
+    pub count: usize,
+    /// The position of the first query.
+    pub first: usize,
+    /// The position of the last query.
+    pub last: usize,
+    /// The first query, as given.
+    pub example: String,
+    /// The duration of each query, in order, if recorded with
+    /// [`Fingerprinter::group_many_with_durations`].
+    pub durations: Vec<Duration>,
+}
+
+impl FingerprintGroup {
+    /// The total duration of the queries, or zero if durations weren't recorded.
+    pub fn total_duration(&self) -> Duration {
+        self.durations.iter().sum()
+    }
+}
+
+fn group_fingerprints(
+    queries: &[&str],
+    fingerprints: Vec<String>,
+    durations: Option<&[Duration]>,
+    order: GroupOrder,
+) -> Vec<FingerprintGroup> {
+    let mut groups: Vec<FingerprintGroup> = vec![];
+    let mut positions: HashMap<String, usize> = HashMap::new();
+    for (index, fingerprint) in fingerprints.into_iter().enumerate() {
+        let position = *positions
+            .entry(fingerprint)
+            .or_insert_with_key(|fingerprint| {
+                groups.push(FingerprintGroup {
+                    fingerprint: fingerprint.clone(),
+                    count: 0,
+                    first: index,
+                    last: index,
+                    example: queries[index].to_string(),
+                    durations: vec![],
+                });
+                groups.len() - 1
+            });
+        let group = &mut groups[position];
+        group.count += 1;
+        group.last = index;
+        if let Some(durations) = durations {
+            group.durations.push(durations[index]);
+        }
+    }
+    if order == GroupOrder::Count {
+        // Stable, so ties stay in order of first appearance.
+        groups.sort_by_key(|group| Reverse(group.count));
+    }
+    groups
+}
+
+/// Detects runs of repeated fingerprints, such as the N+1 queries made by an ORM loading
+/// related objects one at a time in a loop.
+///
+/// # Example
+/// ```
+/// use sql_fingerprint::{RepetitionDetector, fingerprint_many};
+///
+/// let fingerprints = fingerprint_many(
+///     vec![
+///         "SELECT * FROM authors",
+///         "SELECT * FROM books WHERE author_id = 1",
+///         "SELECT * FROM books WHERE author_id = 2",
+///         "SELECT * FROM books WHERE author_id = 3",
+///     ],
+///     None,
+/// );
+/// let repeats = RepetitionDetector::new(3).detect(&fingerprints);
+/// assert_eq!(repeats.len(), 1);
+/// assert_eq!(repeats[0].fingerprints, vec!["SELECT * FROM books WHERE ..."]);
+/// assert_eq!(repeats[0].count, 3);
+/// assert_eq!(repeats[0].range, 1..4);
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RepetitionDetector {
+    min_repetitions: usize,
+    max_pattern_length: usize,
+}
+
+impl RepetitionDetector {
+    /// Create a detector for runs of the same fingerprint repeated at least `min_repetitions`
+    /// times, which is raised to 2 if lower.
+    pub fn new(min_repetitions: usize) -> Self {
+        RepetitionDetector {
+            min_repetitions: min_repetitions.max(2),
+            max_pattern_length: 1,
+        }
+    }
+
+    /// Also detect repeated patterns of up to this many fingerprints, such as a parent query
+    /// interleaved with a child query in a loop.
+    /// The shortest repeating pattern is reported.
+    ///
+    /// # Example
+    /// ```
+    /// use sql_fingerprint::RepetitionDetector;
+    ///
+    /// let fingerprints = ["SELECT a", "SELECT b", "SELECT a", "SELECT b", "SELECT a"];
+    /// let repeats = RepetitionDetector::new(2)
+    ///     .max_pattern_length(2)
+    ///     .detect(&fingerprints);
+    /// assert_eq!(repeats[0].fingerprints, vec!["SELECT a", "SELECT b"]);
+    /// assert_eq!(repeats[0].count, 2);
+    /// assert_eq!(repeats[0].range, 0..4);
+    /// ```
+    pub fn max_pattern_length(mut self, length: usize) -> Self {
+        self.max_pattern_length = length.max(1);
+        self
+    }
+
+    /// Detect repeated runs in a sequence of fingerprints, such as from
+    /// [`Fingerprinter::fingerprint_many`], returning them in order.
+    pub fn detect<S: AsRef<str>>(&self, fingerprints: &[S]) -> Vec<RepeatedQueries> {
+        let mut repeats = vec![];
+        let mut start = 0;
+        while start < fingerprints.len() {
+            let found = (1..=self.max_pattern_length).find_map(|length| {
+                let count = count_repetitions(&fingerprints[start..], length);
+                (count >= self.min_repetitions).then_some((length, count))
+            });
+            match found {
+                Some((length, count)) => {
+                    let end = start + length * count;
+                    repeats.push(RepeatedQueries {
+                        fingerprints: fingerprints[start..start + length]
+                            .iter()
+                            .map(|fingerprint| fingerprint.as_ref().to_string())
+                            .collect(),
+                        count,
+                        range: start..end,
+                    });
+                    start = end;
+                }
+                None => start += 1,
+            }
+        }
+        repeats
+    }
+}
+
+/// Count how many times the leading pattern of the given length repeats consecutively.
+fn count_repetitions<S: AsRef<str>>(fingerprints: &[S], length: usize) -> usize {
+    if fingerprints.len() < length {
+        return 0;
+    }
+    let pattern = &fingerprints[..length];
+    fingerprints
+        .chunks_exact(length)
+        .take_while(|chunk| {
+            chunk
+                .iter()
+                .zip(pattern)
+                .all(|(fingerprint, expected)| fingerprint.as_ref() == expected.as_ref())
+        })
+        .count()
+}
+
+/// A run of repeated fingerprints found by [`RepetitionDetector::detect`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct RepeatedQueries {
+    /// The repeated fingerprint, or pattern of fingerprints.
+    pub fingerprints: Vec<String>,
+    /// The number of repetitions.
+    pub count: usize,
+    /// The range of positions of the run in the sequence.
+    pub range: Range<usize>,
+}
+
+/// Diff two sequences of fingerprints, such as the traces of a test before and after a change,
+/// aligning them by their longest common subsequence.
+/// A removed fingerprint that is also added elsewhere is marked as moved.
+///
+/// The diff renders with [`fmt::Display`], one line per fingerprint, followed by any changes in
+/// fingerprint counts.
+///
+/// # Example
+/// ```
+/// use sql_fingerprint::diff_traces;
+///
+/// let diff = diff_traces(
+///     &["SELECT a", "SELECT b", "SELECT c"],
+///     &["SELECT a", "SELECT c", "SELECT c", "SELECT b"],
+/// );
+/// assert_eq!(
+///     diff.to_string(),
+///     "  SELECT a
+/// < SELECT b
+///   SELECT c
+/// + SELECT c
+/// > SELECT b
+///
+/// Count changes:
+///   1 -> 2  SELECT c
+/// ",
+/// );
+/// ```
+pub fn diff_traces<S: AsRef<str>>(before: &[S], after: &[S]) -> TraceDiff {
+    let before: Vec<&str> = before.iter().map(AsRef::as_ref).collect();
+    let after: Vec<&str> = after.iter().map(AsRef::as_ref).collect();
+
+    let prefix = before
+        .iter()
+        .zip(&after)
+        .take_while(|(old, new)| old == new)
+        .count();
+    let suffix = before[prefix..]
+        .iter()
+        .rev()
+        .zip(after[prefix..].iter().rev())
+        .take_while(|(old, new)| old == new)
+        .count();
+    let old = &before[prefix..before.len() - suffix];
+    let new = &after[prefix..after.len() - suffix];
+
+    let line = |change, fingerprint: &str, before, after| TraceDiffLine {
+        change,
+        fingerprint: fingerprint.to_string(),
+        before,
+        after,
+    };
+    let mut lines: Vec<TraceDiffLine> = (0..prefix)
+        .map(|index| {
+            line(
+                TraceChange::Unchanged,
+                before[index],
+                Some(index),
+                Some(index),
+            )
+        })
+        .collect();
+    let mut edits = vec![];
+    align_traces(old, new, (prefix, prefix), &mut edits);
+    lines.extend(edits.into_iter().map(|edit| match edit {
+        TraceEdit::Unchanged(old, new) => {
+            line(TraceChange::Unchanged, before[old], Some(old), Some(new))
+        }
+        TraceEdit::Removed(old) => line(TraceChange::Removed, before[old], Some(old), None),
+        TraceEdit::Added(new) => line(TraceChange::Added, after[new], None, Some(new)),
+    }));
+    lines.extend((0..suffix).map(|offset| {
+        let (old, new) = (
+            before.len() - suffix + offset,
+            after.len() - suffix + offset,
+        );
+        line(TraceChange::Unchanged, before[old], Some(old), Some(new))
+    }));
+
+    // Pair each removal with the first unpaired addition of the same fingerprint.
+    let mut additions: HashMap<&str, VecDeque<usize>> = HashMap::new();
+    for (index, line) in lines.iter().enumerate() {
+        if line.change == TraceChange::Added {
+            additions
+                .entry(line.fingerprint.as_str())
+                .or_default()
+                .push_back(index);
+        }
+    }
+    let mut moves = vec![];
+    for (removed, line) in lines.iter().enumerate() {
+        if line.change == TraceChange::Removed
+            && let Some(added) = additions
+                .get_mut(line.fingerprint.as_str())
+                .and_then(VecDeque::pop_front)
         {
-            if let Some(alias) = alias
-                && !alias.columns.is_empty()
+            moves.push((removed, added));
+        }
+    }
+    for (removed, added) in moves {
+        let (old, new) = (lines[removed].before, lines[added].after);
+        lines[removed].change = TraceChange::MovedFrom;
+        lines[removed].after = new;
+        lines[added].change = TraceChange::MovedTo;
+        lines[added].before = old;
+    }
+
+    // Count in diff order; a move appears once on each side.
+    let mut counts: Vec<FingerprintCountChange> = vec![];
+    let mut positions: HashMap<&str, usize> = HashMap::new();
+    for line in &lines {
+        let position = *positions
+            .entry(line.fingerprint.as_str())
+            .or_insert_with(|| {
+                counts.push(FingerprintCountChange {
+                    fingerprint: line.fingerprint.clone(),
+                    before: 0,
+                    after: 0,
+                });
+                counts.len() - 1
+            });
+        let count = &mut counts[position];
+        match line.change {
+            TraceChange::Unchanged => {
+                count.before += 1;
+                count.after += 1;
+            }
+            TraceChange::Removed | TraceChange::MovedFrom => count.before += 1,
+            TraceChange::Added | TraceChange::MovedTo => count.after += 1,
+        }
+    }
+    counts.retain(|count| count.before != count.after);
+
+    TraceDiff { lines, counts }
+}
+
+/// An edit aligning two traces, with positions in the whole traces.
+enum TraceEdit {
+    Unchanged(usize, usize),
+    Removed(usize),
+    Added(usize),
+}
+
+/// Align two traces by their longest common subsequence, in linear space with Hirschberg's
+/// algorithm, preferring removals before additions.
+fn align_traces(old: &[&str], new: &[&str], offset: (usize, usize), edits: &mut Vec<TraceEdit>) {
+    let (old_offset, new_offset) = offset;
+    let removals = (0..old.len()).map(|i| TraceEdit::Removed(old_offset + i));
+    let additions = (0..new.len()).map(|j| TraceEdit::Added(new_offset + j));
+    match old {
+        [] => edits.extend(additions),
+        _ if new.is_empty() => edits.extend(removals),
+        [fingerprint] => match new.iter().position(|other| other == fingerprint) {
+            Some(j) => {
+                edits.extend((0..j).map(|j| TraceEdit::Added(new_offset + j)));
+                edits.push(TraceEdit::Unchanged(old_offset, new_offset + j));
+                edits.extend((j + 1..new.len()).map(|j| TraceEdit::Added(new_offset + j)));
+            }
+            None => {
+                edits.extend(removals);
+                edits.extend(additions);
+            }
+        },
+        _ => {
+            let middle = old.len() / 2;
+            let forward = lcs_lengths(old[..middle].iter(), new.iter());
+            let backward = lcs_lengths(old[middle..].iter().rev(), new.iter().rev());
+            // Split `new` where the halves' subsequences are longest together, as early as
+            // possible so that removals come first.
+            let split = (0..=new.len())
+                .max_by_key(|&j| (forward[j] + backward[new.len() - j], Reverse(j)))
+                .unwrap_or(0);
+            align_traces(&old[..middle], &new[..split], offset, edits);
+            align_traces(
+                &old[middle..],
+                &new[split..],
+                (old_offset + middle, new_offset + split),
+                edits,
+            );
+        }
+    }
+}
+
+/// The lengths of the longest common subsequences of `old` and each prefix of `new`.
+fn lcs_lengths<'a>(
+    old: impl Iterator<Item = &'a &'a str>,
+    new: impl Iterator<Item = &'a &'a str> + Clone,
+) -> Vec<usize> {
+    let mut row = vec![0; new.clone().count() + 1];
+    for fingerprint in old {
+        let mut diagonal = 0;
+        for (j, other) in new.clone().enumerate() {
+            let above = row[j + 1];
+            row[j + 1] = if fingerprint == other {
+                diagonal + 1
+            } else {
+                above.max(row[j])
+            };
+            diagonal = above;
+        }
+    }
+    row
+}
+
+/// A diff of two sequences of fingerprints, from [`diff_traces`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct TraceDiff {
+    /// The fingerprints of both sequences, interleaved in diff order.
+    pub lines: Vec<TraceDiffLine>,
+    /// The fingerprints whose counts changed, in order of first appearance in the diff.
+    pub counts: Vec<FingerprintCountChange>,
+}
+
+impl TraceDiff {
+    /// Whether the sequences are identical.
+    pub fn is_empty(&self) -> bool {
+        self.lines
+            .iter()
+            .all(|line| line.change == TraceChange::Unchanged)
+    }
+}
+
+impl fmt::Display for TraceDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for line in &self.lines {
+            let marker = match line.change {
+                TraceChange::Unchanged => ' ',
+                TraceChange::Added => '+',
+                TraceChange::Removed => '-',
+                TraceChange::MovedFrom => '<',
+                TraceChange::MovedTo => '>',
+            };
+            writeln!(f, "{} {}", marker, line.fingerprint)?;
+        }
+        if !self.counts.is_empty() {
+            writeln!(f)?;
+            writeln!(f, "Count changes:")?;
+            for count in &self.counts {
+                writeln!(
+                    f,
+                    "  {} -> {}  {}",
+                    count.before, count.after, count.fingerprint
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A line of a [`TraceDiff`].
+/// Positions are indexes into the sequences before and after.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct TraceDiffLine {
+    /// How the fingerprint changed.
+    pub change: TraceChange,
+    /// The fingerprint.
+    pub fingerprint: String,
+    /// The position before, if present before.
+    pub before: Option<usize>,
+    /// The position after, if present after.
+    pub after: Option<usize>,
+}
+
+/// How a fingerprint changed in a [`TraceDiff`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TraceChange {
+    /// Present in both sequences.
+    Unchanged,
+    /// Only present after.
+    Added,
+    /// Only present before.
+    Removed,
+    /// Moved away from this position before, to the paired [`TraceChange::MovedTo`] line.
+    MovedFrom,
+    /// Moved to this position after, from the paired [`TraceChange::MovedFrom`] line.
+    MovedTo,
+}
+
+/// A change in the count of a fingerprint in a [`TraceDiff`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct FingerprintCountChange {
+    /// The fingerprint.
+    pub fingerprint: String,
+    /// The count before.
+    pub before: usize,
+    /// The count after.
+    pub after: usize,
+}
+
+/// Compare an identifier to a name, ignoring case if the identifier is unquoted.
+fn ident_matches(ident: &Ident, name: &str) -> bool {
+    match ident.quote_style {
+        Some(_) => ident.value == name,
+        None => ident.value.eq_ignore_ascii_case(name),
+    }
+}
+
+/// Parse SQL, retrying with unsupported syntax rewritten if the first attempt fails.
+fn parse_sql(dialect: &dyn Dialect, sql: &str) -> Result<Vec<Statement>, ParserError> {
+    Parser::parse_sql(dialect, sql).or_else(|err| {
+        let Ok(mut tokens) = Tokenizer::new(dialect, sql).tokenize() else {
+            return Err(err);
+        };
+        let merged = merge_format_placeholders(&mut tokens);
+        let stripped = strip_conflict_target_predicates(&mut tokens);
+        if !merged && !stripped {
+            return Err(err);
+        }
+        Parser::new(dialect)
+            .with_tokens(tokens)
+            .parse_statements()
+            .map_err(|_| err)
+    })
+}
+
+/// Merge Python format and pyformat bind parameters, `%s` and `%(name)s`, into
+/// placeholder tokens, since sqlparser tokenizes them as the modulo operator.
+fn merge_format_placeholders(tokens: &mut Vec<Token>) -> bool {
+    let is_s = |token: Option<&Token>| matches!(token, Some(Token::Word(word)) if word.quote_style.is_none() && word.value == "s");
+
+    let mut merged = false;
+    let mut i = 0;
+    while i < tokens.len() {
+        if tokens[i] == Token::Mod {
+            if is_s(tokens.get(i + 1)) {
+                tokens.splice(i..i + 2, [Token::Placeholder("%s".to_string())]);
+                merged = true;
+            } else if tokens.get(i + 1) == Some(&Token::LParen)
+                && let Some(Token::Word(name)) = tokens.get(i + 2)
+                && tokens.get(i + 3) == Some(&Token::RParen)
+                && is_s(tokens.get(i + 4))
             {
-                alias.columns = vec![TableAliasColumnDef {
-                    name: Ident::new("..."),
-                    data_type: None,
-                }];
+                let placeholder = format!("%({})s", name.value);
+                tokens.splice(i..i + 5, [Token::Placeholder(placeholder)]);
+                merged = true;
+            }
+        }
+        i += 1;
+    }
+    merged
+}
+
+/// Remove partial index predicates from `ON CONFLICT (...) WHERE ... DO` clauses,
+/// which sqlparser cannot parse.
+/// Conflict targets are elided anyway, so the predicate doesn't affect the fingerprint.
+fn strip_conflict_target_predicates(tokens: &mut Vec<Token>) -> bool {
+    let significant: Vec<usize> = tokens
+        .iter()
+        .enumerate()
+        .filter(|(_, token)| !matches!(token, Token::Whitespace(_)))
+        .map(|(i, _)| i)
+        .collect();
+    let token_at = |i: usize| significant.get(i).map(|&j| &tokens[j]);
+    let keyword_at = |i: usize, keyword: Keyword| matches!(token_at(i), Some(Token::Word(word)) if word.keyword == keyword);
+
+    let mut removed = vec![false; tokens.len()];
+    let mut i = 0;
+    while i < significant.len() {
+        if !(keyword_at(i, Keyword::ON)
+            && keyword_at(i + 1, Keyword::CONFLICT)
+            && token_at(i + 2) == Some(&Token::LParen))
+        {
+            i += 1;
+            continue;
+        }
+
+        // Find the closing parenthesis of the conflict target.
+        let mut depth = 0;
+        i += 2;
+        while let Some(token) = token_at(i) {
+            match token {
+                Token::LParen => depth += 1,
+                Token::RParen => depth -= 1,
+                _ => {}
             }
-            if !array_exprs.is_empty() {
-                *array_exprs = vec![placeholder_value()];
+            i += 1;
+            if depth == 0 {
+                break;
             }
         }
-        ControlFlow::Continue(())
+        if !keyword_at(i, Keyword::WHERE) {
+            continue;
+        }
+
+        // Find the DO keyword that ends the predicate.
+        let start = i;
+        while let Some(token) = token_at(i) {
+            match token {
+                Token::LParen => depth += 1,
+                Token::RParen => depth -= 1,
+                Token::Word(word) if word.keyword == Keyword::DO && depth == 0 => break,
+                _ => {}
+            }
+            i += 1;
+        }
+        if i == significant.len() {
+            return false;
+        }
+        removed[significant[start]..significant[i]].fill(true);
     }
 
-    fn pre_visit_expr(&mut self, _expr: &mut Expr) -> ControlFlow<Self::Break> {
-        match _expr {
-            Expr::Identifier(ident) => {
-                maybe_unquote_ident(ident);
+    if !removed.contains(&true) {
+        return false;
+    }
+    let mut removed = removed.into_iter();
+    tokens.retain(|_| !removed.next().unwrap());
+    true
+}
+
+/// Rename alias definitions to 't1', 't2', etc., returning the renamed aliases so that
+/// references to them can be rewritten.
+fn anonymize_alias_definitions(ast: &mut [Statement]) -> Aliases {
+    let mut relations = RelationNames::default();
+    for stmt in ast.iter() {
+        let _ = stmt.visit(&mut relations);
+    }
+    let mut anonymizer = AliasAnonymizer {
+        aliases: Aliases {
+            reserved: &relations.names - &relations.cte_names,
+            ..Aliases::default()
+        },
+    };
+    for stmt in ast {
+        let _ = stmt.visit(&mut anonymizer);
+    }
+    anonymizer.aliases
+}
+
+/// Renumber already anonymized aliases in order of definition, rewriting references to match.
+fn renumber_aliases(ast: &mut [Statement]) {
+    let mut renumbering = AliasRenumbering {
+        aliases: anonymize_alias_definitions(ast),
+    };
+    for stmt in ast {
+        let _ = stmt.visit(&mut renumbering);
+    }
+}
+
+#[derive(Default)]
+struct Aliases {
+    /// Map of original alias names to anonymized names.
+    names: HashMap<String, String>,
+    /// Original names of CTEs, which can be referenced as relations.
+    cte_names: HashSet<String>,
+    /// Names of relations, which anonymized names skip so that references stay unambiguous.
+    reserved: HashSet<String>,
+    /// The number of the last anonymized name.
+    count: usize,
+}
+
+impl Aliases {
+    fn anonymize(&mut self, ident: &mut Ident) {
+        let alias = match self.names.get(&ident.value) {
+            Some(alias) => alias.clone(),
+            None => {
+                let alias = loop {
+                    self.count += 1;
+                    let alias = format!("t{}", self.count);
+                    if !self.reserved.contains(&alias) {
+                        break alias;
+                    }
+                };
+                self.names.insert(ident.value.clone(), alias.clone());
+                alias
             }
-            Expr::CompoundIdentifier(idents) => {
-                for ident in idents {
-                    maybe_unquote_ident(ident);
-                }
+        };
+        *ident = Ident::new(alias);
+    }
+
+    fn rewrite_reference(&self, ident: &mut Ident) {
+        if let Some(alias) = self.names.get(&ident.value) {
+            *ident = Ident::new(alias.clone());
+        }
+    }
+}
+
+/// Collect the unqualified names of the relations in statements, and of their CTEs.
+#[derive(Default)]
+struct RelationNames {
+    names: HashSet<String>,
+    cte_names: HashSet<String>,
+}
+
+impl Visitor for RelationNames {
+    type Break = ();
+
+    fn pre_visit_query(&mut self, query: &Query) -> ControlFlow<Self::Break> {
+        if let Some(with) = &query.with {
+            for cte in &with.cte_tables {
+                self.cte_names.insert(cte.alias.name.value.clone());
+            }
+        }
+        ControlFlow::Continue(())
+    }
+
+    fn pre_visit_relation(&mut self, relation: &ObjectName) -> ControlFlow<Self::Break> {
+        if let Some(ObjectNamePart::Identifier(ident)) = relation.0.last() {
+            self.names.insert(ident.value.clone());
+        }
+        ControlFlow::Continue(())
+    }
+}
+
+struct AliasAnonymizer {
+    aliases: Aliases,
+}
+
+impl VisitorMut for AliasAnonymizer {
+    type Break = ();
+
+    fn pre_visit_query(&mut self, query: &mut Query) -> ControlFlow<Self::Break> {
+        if let Some(with) = &mut query.with {
+            for cte in &mut with.cte_tables {
+                self.aliases.cte_names.insert(cte.alias.name.value.clone());
+                self.aliases.anonymize(&mut cte.alias.name);
             }
-            _ => {}
         }
         ControlFlow::Continue(())
     }
-}
 
-fn placeholder_value() -> Expr {
-    Expr::Value(ValueWithSpan {
-        value: Value::Placeholder("...".to_string()),
-        span: Span::empty(),
-    })
-}
+    fn post_visit_table_factor(
+        &mut self,
+        table_factor: &mut TableFactor,
+    ) -> ControlFlow<Self::Break> {
+        // Visit after any nested queries, so that aliases are numbered in order of appearance.
+        if let Some(alias) = table_factor_alias(table_factor) {
+            self.aliases.anonymize(&mut alias.name);
+        }
+        ControlFlow::Continue(())
+    }
+}
+
+/// Rewrite references to aliases renumbered by [`renumber_aliases`].
+struct AliasRenumbering {
+    aliases: Aliases,
+}
+
+impl VisitorMut for AliasRenumbering {
+    type Break = ();
+
+    fn pre_visit_select(&mut self, select: &mut Select) -> ControlFlow<Self::Break> {
+        for item in &mut select.projection {
+            if let SelectItem::QualifiedWildcard(
+                SelectItemQualifiedWildcardKind::ObjectName(ObjectName(parts)),
+                _,
+            ) = item
+                && let [ObjectNamePart::Identifier(ident)] = parts.as_mut_slice()
+            {
+                self.aliases.rewrite_reference(ident);
+            }
+        }
+        ControlFlow::Continue(())
+    }
+
+    fn pre_visit_relation(&mut self, relation: &mut ObjectName) -> ControlFlow<Self::Break> {
+        if let [ObjectNamePart::Identifier(ident)] = relation.0.as_mut_slice()
+            && self.aliases.cte_names.contains(&ident.value)
+        {
+            self.aliases.rewrite_reference(ident);
+        }
+        ControlFlow::Continue(())
+    }
+
+    fn pre_visit_expr(&mut self, expr: &mut Expr) -> ControlFlow<Self::Break> {
+        if let Expr::CompoundIdentifier(idents) = expr
+            && idents.len() >= 2
+        {
+            self.aliases.rewrite_reference(&mut idents[0]);
+        }
+        ControlFlow::Continue(())
+    }
+}
+
+fn table_factor_alias(table_factor: &mut TableFactor) -> Option<&mut TableAlias> {
+    match table_factor {
+        TableFactor::Table { alias, .. }
+        | TableFactor::Derived { alias, .. }
+        | TableFactor::TableFunction { alias, .. }
+        | TableFactor::Function { alias, .. }
+        | TableFactor::UNNEST { alias, .. }
+        | TableFactor::JsonTable { alias, .. }
+        | TableFactor::OpenJsonTable { alias, .. }
+        | TableFactor::NestedJoin { alias, .. }
+        | TableFactor::Pivot { alias, .. }
+        | TableFactor::Unpivot { alias, .. }
+        | TableFactor::MatchRecognize { alias, .. }
+        | TableFactor::XmlTable { alias, .. } => alias.as_mut(),
+        _ => None,
+    }
+}
+
+struct FingerprintingVisitor<'a> {
+    options: &'a Fingerprinter<'a>,
+    savepoint_ids: HashMap<String, String>,
+    aliases: Aliases,
+    /// The names of the CTEs in scope, per query being visited.
+    cte_scopes: Vec<HashSet<String>>,
+    /// Whether the next relation visited names a table-valued function, like `generate_series`.
+    in_table_function: bool,
+    /// Whether the next expression visited is the parenthesized root of a field access, like
+    /// `(g(x))` in `(g(x)).y`, whose parentheses are required.
+    in_field_access_root: bool,
+    /// The original text of each elision, if capturing, indexed by the ID stored in the
+    /// placeholder's span.
+    elided: Option<Vec<String>>,
+}
+
+impl<'a> FingerprintingVisitor<'a> {
+    fn new(options: &'a Fingerprinter<'a>) -> Self {
+        FingerprintingVisitor {
+            options,
+            savepoint_ids: HashMap::new(),
+            aliases: Aliases::default(),
+            cte_scopes: vec![],
+            in_table_function: false,
+            in_field_access_root: false,
+            elided: None,
+        }
+    }
+
+    /// Record the original text of an elision if capturing, returning the span to tag its
+    /// placeholder with.
+    fn elide(&mut self, original: impl fmt::Display) -> Span {
+        match &mut self.elided {
+            Some(elided) => {
+                elided.push(original.to_string());
+                elision_span(elided.len())
+            }
+            None => Span::empty(),
+        }
+    }
+
+    fn elided_value(&mut self, original: impl fmt::Display) -> Expr {
+        Expr::Value(self.elided_value_with_span(original))
+    }
+
+    fn elided_value_with_span(&mut self, original: impl fmt::Display) -> ValueWithSpan {
+        ValueWithSpan {
+            span: self.elide(original),
+            ..placeholder_value_with_span()
+        }
+    }
+
+    fn elided_ident(&mut self, original: impl fmt::Display) -> Ident {
+        Ident::with_span(self.elide(original), "...")
+    }
+
+    fn elided_sets(&mut self, sets: &[Vec<Expr>]) -> Vec<Vec<Expr>> {
+        let original = separated(sets.iter().map(|set| GroupingSet(set)), ", ");
+        vec![vec![self.elided_value(original)]]
+    }
+
+    fn elided_assignments(&mut self, assignments: &[Assignment]) -> Vec<Assignment> {
+        let targets = separated(assignments.iter().map(|a| &a.target), ", ");
+        let values = separated(assignments.iter().map(|a| &a.value), ", ");
+        vec![Assignment {
+            target: AssignmentTarget::ColumnName(ObjectName(vec![ObjectNamePart::Identifier(
+                self.elided_ident(targets),
+            )])),
+            value: self.elided_value(values),
+        }]
+    }
+
+    fn elided_returning(&mut self, returning: &mut Option<Vec<SelectItem>>) {
+        if let Some(returning) = returning
+            && !returning.is_empty()
+        {
+            let value = self.elided_value(comma_separated(returning));
+            *returning = vec![SelectItem::UnnamedExpr(value)];
+        }
+    }
+
+    fn elide_selection(&mut self, selection: &mut Option<Expr>) {
+        if let Some(selection) = selection {
+            *selection = self.elided_value(&*selection);
+        }
+    }
+
+    fn visit_select(&mut self, select: &mut sqlparser::ast::Select) {
+        if !select.projection.is_empty() {
+            match &mut select.projection[0] {
+                SelectItem::UnnamedExpr(_) | SelectItem::ExprWithAlias { .. } => {
+                    let value = self.elided_value(comma_separated(&select.projection));
+                    select.projection[0] = SelectItem::UnnamedExpr(value);
+                }
+                SelectItem::QualifiedWildcard(
+                    SelectItemQualifiedWildcardKind::ObjectName(ObjectName(parts)),
+                    _,
+                ) => {
+                    if let [ObjectNamePart::Identifier(ident)] = parts.as_mut_slice() {
+                        self.aliases.rewrite_reference(ident);
+                    }
+                }
+                _ => {}
+            }
+            select.projection.truncate(1);
+        }
+
+        if let Some(Top {
+            quantity: Some(quantity),
+            ..
+        }) = &mut select.top
+        {
+            let value = match quantity {
+                TopQuantity::Expr(expr) => self.elided_value(&*expr),
+                TopQuantity::Constant(constant) => self.elided_value(*constant),
+            };
+            *quantity = TopQuantity::Expr(value);
+        }
+
+        if let Some(Distinct::On(exprs)) = &mut select.distinct
+            && !exprs.is_empty()
+        {
+            *exprs = vec![self.elided_value(comma_separated(exprs))];
+        };
+
+        self.visit_tables_with_joins(&mut select.from);
+
+        self.elide_selection(&mut select.selection);
+
+        let modifiers = match &mut select.group_by {
+            GroupByExpr::All(modifiers) => modifiers,
+            GroupByExpr::Expressions(col_names, modifiers) => {
+                if !col_names.is_empty() {
+                    // Keep grouping constructs visible, collapsing runs of other expressions.
+                    let mut new_col_names: Vec<Expr> = vec![];
+                    let mut run: Vec<Expr> = vec![];
+                    for col_name in col_names.drain(..) {
+                        let new_col_name = match &col_name {
+                            Expr::Rollup(sets) => Expr::Rollup(self.elided_sets(sets)),
+                            Expr::Cube(sets) => Expr::Cube(self.elided_sets(sets)),
+                            Expr::GroupingSets(sets) => Expr::GroupingSets(self.elided_sets(sets)),
+                            _ => {
+                                run.push(col_name);
+                                continue;
+                            }
+                        };
+                        if !run.is_empty() {
+                            new_col_names.push(self.elided_value(comma_separated(&run)));
+                            run.clear();
+                        }
+                        if new_col_names.last() != Some(&new_col_name) {
+                            new_col_names.push(new_col_name);
+                        }
+                    }
+                    if !run.is_empty() {
+                        new_col_names.push(self.elided_value(comma_separated(&run)));
+                    }
+                    *col_names = new_col_names;
+                }
+                modifiers
+            }
+        };
+        for modifier in modifiers {
+            if let GroupByWithModifier::GroupingSets(expr) = modifier {
+                let sets = match expr {
+                    Expr::GroupingSets(sets) => self.elided_sets(sets),
+                    _ => vec![vec![self.elided_value(&*expr)]],
+                };
+                *expr = Expr::GroupingSets(sets);
+            }
+        }
+    }
+
+    fn visit_relation_name(&mut self, name: &mut ObjectName) {
+        if let [ObjectNamePart::Identifier(ident)] = name.0.as_mut_slice() {
+            if self.aliases.cte_names.contains(&ident.value) {
+                self.aliases.rewrite_reference(ident);
+                return;
+            }
+            // References to CTEs aren't tables, so aren't qualified or rewritten.
+            if self
+                .cte_scopes
+                .iter()
+                .any(|scope| scope.contains(&ident.value))
+            {
+                self.options.maybe_unquote_ident(ident);
+                return;
+            }
+        }
+        self.options.normalize_relation_name(name);
+    }
+
+    fn visit_tables_with_joins(&mut self, tables: &mut [TableWithJoins]) {
+        for table_with_joins in tables {
+            for join in &mut table_with_joins.joins {
+                if self.options.canonicalize_syntax {
+                    canonicalize_join_operator(&mut join.join_operator);
+                }
+                match &mut join.join_operator {
+                    JoinOperator::Join(constraint)
+                    | JoinOperator::Inner(constraint)
+                    | JoinOperator::Left(constraint)
+                    | JoinOperator::LeftOuter(constraint)
+                    | JoinOperator::Right(constraint)
+                    | JoinOperator::RightOuter(constraint)
+                    | JoinOperator::FullOuter(constraint)
+                    | JoinOperator::Semi(constraint)
+                    | JoinOperator::LeftSemi(constraint)
+                    | JoinOperator::RightSemi(constraint)
+                    | JoinOperator::Anti(constraint)
+                    | JoinOperator::LeftAnti(constraint)
+                    | JoinOperator::RightAnti(constraint) => {
+                        if let JoinConstraint::On(expr) = constraint {
+                            *expr = self.elided_value(&*expr);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+}
+
+impl VisitorMut for FingerprintingVisitor<'_> {
+    type Break = ();
+
+    fn pre_visit_statement(&mut self, stmt: &mut Statement) -> ControlFlow<Self::Break> {
+        match stmt {
+            Statement::StartTransaction {
+                begin,
+                transaction,
+                modifier: None,
+                statements,
+                exception: None,
+                has_end_keyword: false,
+                ..
+            } if self.options.canonicalize_syntax && statements.is_empty() => {
+                *begin = true;
+                *transaction = None;
+            }
+            Statement::Commit {
+                end,
+                modifier: None,
+                ..
+            } if self.options.canonicalize_syntax => {
+                *end = false;
+            }
+            Statement::Savepoint { name } => {
+                let savepoint_id = format!("s{}", self.savepoint_ids.len() + 1);
+                self.savepoint_ids
+                    .insert(name.value.clone(), savepoint_id.clone());
+                *name = Ident::new(savepoint_id);
+            }
+            Statement::ReleaseSavepoint { name } => {
+                if let Some(savepoint_id) = self.savepoint_ids.get(&name.value).cloned() {
+                    *name = Ident::new(savepoint_id);
+                }
+            }
+            Statement::Rollback {
+                savepoint: Some(name),
+                ..
+            } => {
+                if let Some(savepoint_id) = self.savepoint_ids.get(&name.value).cloned() {
+                    *name = Ident::new(savepoint_id);
+                }
+            }
+            Statement::Declare { stmts } => {
+                for stmt in stmts {
+                    if !stmt.names.is_empty() {
+                        stmt.names = vec![self.elided_ident(comma_separated(&stmt.names))];
+                    }
+                }
+            }
+            Statement::Insert(Insert {
+                columns,
+                source,
+                assignments,
+                on,
+                returning,
+                ..
+            }) => {
+                if !columns.is_empty() {
+                    *columns = vec![self.elided_ident(comma_separated(columns)).into()];
+                }
+                if let Some(source) = source
+                    && let SetExpr::Values(values) = source.as_mut().body.as_mut()
+                {
+                    // Join rows so that the original text replaces the placeholder in `(...)`.
+                    let original = separated(
+                        values.rows.iter().map(|row| comma_separated(&row.content)),
+                        "), (",
+                    );
+                    values.rows = vec![Parens::with_empty_span(vec![self.elided_value(original)])];
+                }
+                if !assignments.is_empty() {
+                    *assignments = self.elided_assignments(assignments);
+                }
+                if let Some(OnInsert::DuplicateKeyUpdate(assignments)) = on
+                    && !assignments.is_empty()
+                {
+                    *assignments = self.elided_assignments(assignments);
+                }
+                if let Some(OnInsert::OnConflict(OnConflict {
+                    conflict_target,
+                    action,
+                })) = on
+                {
+                    match conflict_target {
+                        Some(ConflictTarget::Columns(columns)) if !columns.is_empty() => {
+                            *columns = vec![self.elided_ident(comma_separated(columns))];
+                        }
+                        Some(ConflictTarget::OnConstraint(name)) => {
+                            self.options.maybe_unquote_object_name(name);
+                        }
+                        _ => {}
+                    }
+                    if let OnConflictAction::DoUpdate(DoUpdate {
+                        assignments,
+                        selection,
+                    }) = action
+                    {
+                        if !assignments.is_empty() {
+                            *assignments = self.elided_assignments(assignments);
+                        }
+                        self.elide_selection(selection);
+                    }
+                }
+                self.elided_returning(returning);
+            }
+            Statement::Update(Update {
+                table,
+                assignments,
+                from,
+                selection,
+                returning,
+                ..
+            }) => {
+                self.visit_tables_with_joins(std::slice::from_mut(table));
+                if let Some(
+                    UpdateTableFromKind::BeforeSet(from) | UpdateTableFromKind::AfterSet(from),
+                ) = from
+                {
+                    self.visit_tables_with_joins(from);
+                }
+                if !assignments.is_empty() {
+                    *assignments = self.elided_assignments(assignments);
+                }
+                self.elide_selection(selection);
+                self.elided_returning(returning);
+            }
+            Statement::Delete(Delete {
+                tables,
+                from,
+                using,
+                selection,
+                returning,
+                ..
+            }) => {
+                for table in tables {
+                    self.visit_relation_name(table);
+                }
+                let (FromTable::WithFromKeyword(from) | FromTable::WithoutKeyword(from)) = from;
+                self.visit_tables_with_joins(from);
+                if let Some(using) = using {
+                    self.visit_tables_with_joins(using);
+                }
+                self.elide_selection(selection);
+                self.elided_returning(returning);
+            }
+            Statement::Drop {
+                object_type,
+                names,
+                table,
+                ..
+            } => {
+                if matches!(
+                    object_type,
+                    ObjectType::Table | ObjectType::View | ObjectType::MaterializedView
+                ) {
+                    for name in names {
+                        self.visit_relation_name(name);
+                    }
+                }
+                if let Some(table) = table {
+                    self.visit_relation_name(table);
+                }
+            }
+            _ => {}
+        }
+        ControlFlow::Continue(())
+    }
+
+    fn pre_visit_query(&mut self, query: &mut Query) -> ControlFlow<Self::Break> {
+        if self.options.canonicalize_syntax {
+            canonicalize_row_limit(query);
+        }
+        if self.options.remove_redundant_parentheses {
+            while let SetExpr::Query(inner) = query.body.as_ref() {
+                if is_bare_query(inner) {
+                    query.body = inner.body.clone();
+                } else if is_bare_query(query) {
+                    *query = inner.as_ref().clone();
+                } else {
+                    break;
+                }
+            }
+            remove_set_operand_parentheses(query.body.as_mut());
+        }
+        self.cte_scopes.push(
+            query
+                .with
+                .iter()
+                .flat_map(|with| &with.cte_tables)
+                .map(|cte| cte.alias.name.value.clone())
+                .collect(),
+        );
+        match query.body.as_mut() {
+            SetExpr::Select(select) => {
+                self.visit_select(select);
+            }
+            SetExpr::SetOperation {
+                left,
+                right,
+                set_quantifier,
+                ..
+            } => {
+                if self.options.canonicalize_syntax {
+                    canonicalize_set_quantifier(set_quantifier);
+                }
+                // push left and right into a double-ended queue to visit them,
+                // expnading left and right as required.
+                let mut stack = vec![left.as_mut(), right.as_mut()];
+                while let Some(set_expr) = stack.pop() {
+                    match set_expr {
+                        SetExpr::Select(select) => {
+                            self.visit_select(select);
+                        }
+                        SetExpr::SetOperation {
+                            left,
+                            right,
+                            set_quantifier,
+                            ..
+                        } => {
+                            if self.options.canonicalize_syntax {
+                                canonicalize_set_quantifier(set_quantifier);
+                            }
+                            // Push left and right onto the stack for further processing.
+                            stack.push(left.as_mut());
+                            stack.push(right.as_mut());
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            _ => {}
+        }
+        if let Some(order_by) = &mut query.order_by {
+            let OrderBy { kind, .. } = order_by;
+            if let OrderByKind::Expressions(expressions) = kind
+                && !expressions.is_empty()
+            {
+                // The first expression keeps its options, so record only its expression.
+                let first: &dyn fmt::Display = &expressions[0].expr;
+                let rest = expressions[1..]
+                    .iter()
+                    .map(|expr| expr as &dyn fmt::Display);
+                let original = separated(iter::once(first).chain(rest), ", ");
+                expressions[0].expr = self.elided_value(original);
+                expressions.truncate(1);
+            }
+        }
+        if let Some(limit_clause) = &mut query.limit_clause {
+            match limit_clause {
+                LimitClause::LimitOffset {
+                    limit,
+                    offset,
+                    limit_by,
+                } => {
+                    if let Some(limit_value) = limit {
+                        *limit_value = self.elided_value(&*limit_value);
+                    }
+                    if let Some(Offset { value, .. }) = offset {
+                        *value = self.elided_value(&*value);
+                    }
+                    if !limit_by.is_empty() {
+                        *limit_by = vec![self.elided_value(comma_separated(limit_by))];
+                    }
+                }
+                // MySQL specific, needs testing!Ó
+                LimitClause::OffsetCommaLimit { offset, limit } => {
+                    *offset = self.elided_value(&*offset);
+                    *limit = self.elided_value(&*limit);
+                }
+            }
+        }
+        if let Some(Fetch {
+            quantity: Some(quantity),
+            ..
+        }) = &mut query.fetch
+        {
+            *quantity = self.elided_value(&*quantity);
+        }
+        ControlFlow::Continue(())
+    }
+
+    fn post_visit_query(&mut self, query: &mut Query) -> ControlFlow<Self::Break> {
+        self.cte_scopes.pop();
+        // Collapse after visiting, so that branches are compared once nested queries have been
+        // fingerprinted too.
+        if self.options.collapse_union_all {
+            collapse_union_all_branches(query.body.as_mut());
+        }
+        ControlFlow::Continue(())
+    }
+
+    fn post_visit_select(&mut self, select: &mut Select) -> ControlFlow<Self::Break> {
+        if self.options.sort_commutative_operands {
+            sort_from_tables(&mut select.from);
+        }
+        ControlFlow::Continue(())
+    }
+
+    fn pre_visit_relation(&mut self, relation: &mut ObjectName) -> ControlFlow<Self::Break> {
+        if self.in_table_function {
+            // Function names aren't tables, so aren't qualified or rewritten.
+            self.in_table_function = false;
+            self.options.maybe_unquote_object_name(relation);
+        } else {
+            self.visit_relation_name(relation);
+        }
+        ControlFlow::Continue(())
+    }
+
+    fn pre_visit_table_factor(
+        &mut self,
+        table_factor: &mut TableFactor,
+    ) -> ControlFlow<Self::Break> {
+        if self.options.canonicalize_syntax
+            && let Some(alias) = table_factor_alias(table_factor)
+        {
+            // Oracle rejects `AS` before table aliases.
+            alias.explicit = !self.options.dialect.is::<OracleDialect>();
+        }
+        // The name is visited next, as a relation.
+        self.in_table_function = matches!(table_factor, TableFactor::Table { args: Some(_), .. });
+        match table_factor {
+            TableFactor::Table {
+                sample: Some(sample),
+                ..
+            } => {
+                let (TableSampleKind::BeforeTableAlias(sample)
+                | TableSampleKind::AfterTableAlias(sample)) = sample;
+                let TableSample {
+                    quantity,
+                    seed,
+                    bucket,
+                    offset,
+                    ..
+                } = sample.as_mut();
+                if let Some(TableSampleQuantity { value, .. }) = quantity {
+                    *value = self.elided_value(&*value);
+                }
+                if let Some(TableSampleSeed { value, .. }) = seed {
+                    *value = self.elided_value_with_span(&*value);
+                }
+                if let Some(TableSampleBucket { bucket, total, on }) = bucket {
+                    *bucket = self.elided_value_with_span(&*bucket);
+                    *total = self.elided_value_with_span(&*total);
+                    if let Some(on) = on {
+                        *on = self.elided_value(&*on);
+                    }
+                }
+                if let Some(offset) = offset {
+                    *offset = self.elided_value(&*offset);
+                }
+            }
+            TableFactor::NestedJoin {
+                table_with_joins, ..
+            } => {
+                self.visit_tables_with_joins(std::slice::from_mut(table_with_joins));
+            }
+            TableFactor::UNNEST {
+                alias, array_exprs, ..
+            } => {
+                if let Some(alias) = alias
+                    && !alias.columns.is_empty()
+                {
+                    alias.columns = vec![TableAliasColumnDef {
+                        name: self.elided_ident(comma_separated(&alias.columns)),
+                        data_type: None,
+                    }];
+                }
+                if !array_exprs.is_empty() {
+                    *array_exprs = vec![self.elided_value(comma_separated(array_exprs))];
+                }
+            }
+            _ => {}
+        }
+        ControlFlow::Continue(())
+    }
+
+    fn pre_visit_expr(&mut self, expr: &mut Expr) -> ControlFlow<Self::Break> {
+        let in_field_access_root = std::mem::take(&mut self.in_field_access_root);
+        if self.options.remove_redundant_parentheses && !in_field_access_root {
+            while let Expr::Nested(inner) = expr
+                && matches!(
+                    inner.as_ref(),
+                    Expr::Value(_)
+                        | Expr::Identifier(_)
+                        | Expr::CompoundIdentifier(_)
+                        | Expr::Function(_)
+                        | Expr::Nested(_)
+                        | Expr::Subquery(_)
+                )
+            {
+                *expr = inner.as_ref().clone();
+            }
+        }
+        // The root is visited next.
+        self.in_field_access_root = matches!(
+            expr,
+            Expr::CompoundFieldAccess { root, .. } | Expr::JsonAccess { value: root, .. }
+                if matches!(root.as_ref(), Expr::Nested(_))
+        );
+        match expr {
+            Expr::Identifier(ident)
+                if self.options.placeholder_style.is_some() && is_driver_parameter(ident) =>
+            {
+                *expr = Expr::Value(ValueWithSpan {
+                    value: Value::Placeholder(ident.value.clone()),
+                    span: ident.span,
+                });
+            }
+            Expr::Identifier(ident) => {
+                self.options.maybe_unquote_ident(ident);
+            }
+            Expr::CompoundIdentifier(idents) => {
+                if idents.len() >= 2 {
+                    self.aliases.rewrite_reference(&mut idents[0]);
+                }
+                if let Some(qualification) = &self.options.schema_qualification {
+                    qualification.apply_to_compound_identifier(idents);
+                }
+                for ident in idents.iter_mut() {
+                    self.options.maybe_unquote_ident(ident);
+                }
+                if let Some((_, qualifiers)) = idents.split_last_mut() {
+                    for ident in qualifiers {
+                        self.options.rewrite_relation_name_part(ident);
+                    }
+                }
+            }
+            _ => {}
+        }
+        ControlFlow::Continue(())
+    }
+
+    fn post_visit_expr(&mut self, expr: &mut Expr) -> ControlFlow<Self::Break> {
+        // Sort after visiting, so that operands are compared in their fingerprinted form.
+        if self.options.sort_commutative_operands {
+            sort_commutative_operands(expr);
+        }
+        ControlFlow::Continue(())
+    }
+}
+
+struct PlaceholderNumbering {
+    style: PlaceholderStyle,
+    /// The number given to each numbered or named parameter, so reuses keep their number.
+    numbers: HashMap<String, usize>,
+    count: usize,
+}
+
+impl VisitorMut for PlaceholderNumbering {
+    type Break = ();
+
+    fn pre_visit_value(&mut self, value: &mut ValueWithSpan) -> ControlFlow<Self::Break> {
+        if let Value::Placeholder(placeholder) = &mut value.value
+            && placeholder != "..."
+        {
+            let number = if matches!(placeholder.as_str(), "?" | "%s") {
+                // Anonymous parameters are bound by position, so are always distinct.
+                self.count += 1;
+                self.count
+            } else {
+                *self.numbers.entry(placeholder.clone()).or_insert_with(|| {
+                    self.count += 1;
+                    self.count
+                })
+            };
+            *placeholder = self.style.placeholder(number);
+        }
+        ControlFlow::Continue(())
+    }
+}
+
+/// Tag the span of a placeholder with an elision ID, on line 0, which parsed spans never use.
+fn elision_span(id: usize) -> Span {
+    let location = Location::new(0, id as u64);
+    Span::new(location, location)
+}
+
+fn elision_id(span: Span) -> Option<usize> {
+    (span.start.line == 0 && span.start.column > 0).then_some(span.start.column as usize)
+}
+
+/// Replace tagged placeholders with their elision IDs, surrounded by a marker.
+struct ElisionMarking {
+    marker: char,
+}
+
+impl ElisionMarking {
+    fn mark(&self, id: usize) -> String {
+        format!("{0}{id}{0}", self.marker)
+    }
+
+    fn mark_ident(&self, ident: &mut Ident) {
+        if let Some(id) = elision_id(ident.span) {
+            ident.value = self.mark(id);
+        }
+    }
+
+    fn mark_assignments(&self, assignments: &mut [Assignment]) {
+        for assignment in assignments {
+            if let AssignmentTarget::ColumnName(ObjectName(parts)) = &mut assignment.target {
+                for part in parts {
+                    if let ObjectNamePart::Identifier(ident) = part {
+                        self.mark_ident(ident);
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl VisitorMut for ElisionMarking {
+    type Break = ();
+
+    fn pre_visit_statement(&mut self, stmt: &mut Statement) -> ControlFlow<Self::Break> {
+        match stmt {
+            Statement::Declare { stmts } => {
+                for stmt in stmts {
+                    stmt.names
+                        .iter_mut()
+                        .for_each(|ident| self.mark_ident(ident));
+                }
+            }
+            Statement::Insert(Insert {
+                columns,
+                assignments,
+                on,
+                ..
+            }) => {
+                for ObjectName(parts) in columns {
+                    for part in parts {
+                        if let ObjectNamePart::Identifier(ident) = part {
+                            self.mark_ident(ident);
+                        }
+                    }
+                }
+                self.mark_assignments(assignments);
+                match on {
+                    Some(OnInsert::DuplicateKeyUpdate(assignments)) => {
+                        self.mark_assignments(assignments);
+                    }
+                    Some(OnInsert::OnConflict(OnConflict {
+                        conflict_target,
+                        action,
+                    })) => {
+                        if let Some(ConflictTarget::Columns(columns)) = conflict_target {
+                            columns.iter_mut().for_each(|ident| self.mark_ident(ident));
+                        }
+                        if let OnConflictAction::DoUpdate(DoUpdate { assignments, .. }) = action {
+                            self.mark_assignments(assignments);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            Statement::Update(Update { assignments, .. }) => {
+                self.mark_assignments(assignments);
+            }
+            _ => {}
+        }
+        ControlFlow::Continue(())
+    }
+
+    fn pre_visit_table_factor(
+        &mut self,
+        table_factor: &mut TableFactor,
+    ) -> ControlFlow<Self::Break> {
+        if let TableFactor::UNNEST {
+            alias: Some(alias), ..
+        } = table_factor
+        {
+            for column in &mut alias.columns {
+                self.mark_ident(&mut column.name);
+            }
+        }
+        ControlFlow::Continue(())
+    }
+
+    fn pre_visit_value(&mut self, value: &mut ValueWithSpan) -> ControlFlow<Self::Break> {
+        if let Some(id) = elision_id(value.span) {
+            value.value = Value::Placeholder(self.mark(id));
+        }
+        ControlFlow::Continue(())
+    }
+}
+
+/// Display items with a separator, formatting them only when displayed, so that elisions
+/// aren't rendered unless their original text is captured.
+fn separated<I>(items: I, separator: &'static str) -> Separated<I::IntoIter>
+where
+    I: IntoIterator,
+    I::IntoIter: Clone,
+{
+    Separated {
+        items: items.into_iter(),
+        separator,
+    }
+}
+
+fn comma_separated<T: fmt::Display>(items: &[T]) -> Separated<std::slice::Iter<'_, T>> {
+    separated(items, ", ")
+}
+
+struct Separated<I> {
+    items: I,
+    separator: &'static str,
+}
+
+impl<I> fmt::Display for Separated<I>
+where
+    I: Iterator + Clone,
+    I::Item: fmt::Display,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (index, item) in self.items.clone().enumerate() {
+            if index > 0 {
+                f.write_str(self.separator)?;
+            }
+            write!(f, "{item}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Display a set in `GROUPING SETS`, `CUBE`, or `ROLLUP`, parenthesizing unless it has a single
+/// expression.
+struct GroupingSet<'a>(&'a [Expr]);
+
+impl fmt::Display for GroupingSet<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            [expr] => write!(f, "{expr}"),
+            set => write!(f, "({})", comma_separated(set)),
+        }
+    }
+}
+
+/// Classify a statement, returning its kind and whether it only reads data.
+fn classify_statement(statement: &Statement) -> (StatementKind, bool) {
+    match statement {
+        Statement::Query(query) => classify_query(query),
+        Statement::Insert(insert) => {
+            let upsert = insert.replace_into
+                || matches!(insert.or, Some(SqliteOnConflict::Replace))
+                || matches!(
+                    &insert.on,
+                    Some(OnInsert::DuplicateKeyUpdate(_))
+                        | Some(OnInsert::OnConflict(OnConflict {
+                            action: OnConflictAction::DoUpdate(_),
+                            ..
+                        }))
+                );
+            if upsert {
+                (StatementKind::Upsert, false)
+            } else {
+                (StatementKind::Insert, false)
+            }
+        }
+        Statement::Update(_) => (StatementKind::Update, false),
+        Statement::Delete(_) => (StatementKind::Delete, false),
+        Statement::Merge(_) => (StatementKind::Merge, false),
+        Statement::StartTransaction { .. }
+        | Statement::Commit { .. }
+        | Statement::Rollback {
+            savepoint: None, ..
+        }
+        | Statement::Set(Set::SetTransaction { .. }) => (StatementKind::TransactionControl, true),
+        Statement::Savepoint { .. }
+        | Statement::ReleaseSavepoint { .. }
+        | Statement::Rollback {
+            savepoint: Some(_), ..
+        } => (StatementKind::Savepoint, true),
+        Statement::Set(_)
+        | Statement::Reset(_)
+        | Statement::Use(_)
+        | Statement::Discard { .. }
+        | Statement::AlterSession { .. } => (StatementKind::Session, true),
+        Statement::CreateView(_)
+        | Statement::CreateTable(_)
+        | Statement::CreateVirtualTable { .. }
+        | Statement::CreateIndex(_)
+        | Statement::CreatePolicy(_)
+        | Statement::CreateExtension(_)
+        | Statement::CreateCollation(_)
+        | Statement::CreateSchema { .. }
+        | Statement::CreateDatabase { .. }
+        | Statement::CreateFunction(_)
+        | Statement::CreateTrigger(_)
+        | Statement::CreateProcedure { .. }
+        | Statement::CreateMacro { .. }
+        | Statement::CreateSequence { .. }
+        | Statement::CreateDomain(_)
+        | Statement::CreateType { .. }
+        | Statement::AlterTable(_)
+        | Statement::AlterSchema(_)
+        | Statement::AlterIndex { .. }
+        | Statement::AlterView { .. }
+        | Statement::AlterFunction(_)
+        | Statement::AlterType(_)
+        | Statement::AlterCollation(_)
+        | Statement::AlterPolicy(_)
+        | Statement::Drop { .. }
+        | Statement::DropFunction(_)
+        | Statement::DropDomain(_)
+        | Statement::DropProcedure { .. }
+        | Statement::DropPolicy(_)
+        | Statement::DropExtension(_)
+        | Statement::DropTrigger(_)
+        | Statement::Truncate(_)
+        | Statement::RenameTable(_)
+        | Statement::Comment { .. } => (StatementKind::Ddl, false),
+        Statement::Explain {
+            analyze, statement, ..
+        } => (
+            StatementKind::Utility,
+            // `EXPLAIN ANALYZE` runs the statement.
+            !analyze || classify_statement(statement).1,
+        ),
+        Statement::Copy { to, .. } => (StatementKind::Utility, *to),
+        Statement::ExplainTable { .. }
+        | Statement::ShowFunctions { .. }
+        | Statement::ShowVariable { .. }
+        | Statement::ShowStatus { .. }
+        | Statement::ShowVariables { .. }
+        | Statement::ShowCreate { .. }
+        | Statement::ShowColumns { .. }
+        | Statement::ShowCatalogs { .. }
+        | Statement::ShowDatabases { .. }
+        | Statement::ShowProcessList { .. }
+        | Statement::ShowSchemas { .. }
+        | Statement::ShowCharset(_)
+        | Statement::ShowObjects(_)
+        | Statement::ShowTables { .. }
+        | Statement::ShowViews { .. }
+        | Statement::ShowCollation { .. } => (StatementKind::Utility, true),
+        _ => (StatementKind::Utility, false),
+    }
+}
+
+fn classify_query(query: &Query) -> (StatementKind, bool) {
+    let (kind, read_only) = match query.body.as_ref() {
+        SetExpr::Insert(statement)
+        | SetExpr::Update(statement)
+        | SetExpr::Delete(statement)
+        | SetExpr::Merge(statement) => classify_statement(statement),
+        SetExpr::Select(select) => (StatementKind::Select, select.into.is_none()),
+        _ => (StatementKind::Select, true),
+    };
+    // Data-modifying CTEs, like `WITH x AS (DELETE ... RETURNING *) SELECT ...`, make the
+    // whole query write.
+    let ctes_read_only = query
+        .with
+        .iter()
+        .flat_map(|with| &with.cte_tables)
+        .all(|cte| classify_query(&cte.query).1);
+    (kind, read_only && ctes_read_only && query.locks.is_empty())
+}
+
+/// Classify a statement from its tokens, for unparsable SQL, on a best-effort basis.
+fn classify_tokens(tokens: &[Token]) -> (StatementKind, bool) {
+    // Keywords of the first statement, with their parenthesis depth.
+    let mut keywords = vec![];
+    let mut depth = 0usize;
+    for token in tokens {
+        match token {
+            Token::LParen => depth += 1,
+            Token::RParen => depth = depth.saturating_sub(1),
+            Token::SemiColon if depth == 0 => break,
+            Token::Word(word) => keywords.push((word.keyword, depth)),
+            _ => {}
+        }
+    }
+    classify_keywords(&keywords)
+}
+
+fn classify_keywords(keywords: &[(Keyword, usize)]) -> (StatementKind, bool) {
+    let contains = |sequence: &[Keyword]| {
+        keywords.windows(sequence.len()).any(|window| {
+            window
+                .iter()
+                .zip(sequence)
+                .all(|((keyword, _), expected)| keyword == expected)
+        })
+    };
+    let Some(((first, depth), rest)) = keywords.split_first() else {
+        return (StatementKind::Unknown, false);
+    };
+    let second = rest.first().map(|(keyword, _)| *keyword);
+    match first {
+        Keyword::WITH => {
+            // Classify by the statement after the CTEs.
+            match rest.iter().position(|(keyword, keyword_depth)| {
+                keyword_depth == depth
+                    && matches!(
+                        keyword,
+                        Keyword::SELECT
+                            | Keyword::VALUES
+                            | Keyword::INSERT
+                            | Keyword::UPDATE
+                            | Keyword::DELETE
+                            | Keyword::MERGE
+                    )
+            }) {
+                Some(position) => {
+                    let (kind, read_only) = classify_keywords(&rest[position..]);
+                    let ctes_read_only = !rest[..position].iter().any(|(keyword, _)| {
+                        matches!(
+                            keyword,
+                            Keyword::INSERT | Keyword::UPDATE | Keyword::DELETE | Keyword::MERGE
+                        )
+                    });
+                    (kind, read_only && ctes_read_only)
+                }
+                None => (StatementKind::Unknown, false),
+            }
+        }
+        Keyword::SELECT | Keyword::VALUES | Keyword::TABLE => {
+            let locking = contains(&[Keyword::FOR, Keyword::UPDATE])
+                || contains(&[Keyword::FOR, Keyword::SHARE])
+                || contains(&[Keyword::FOR, Keyword::NO])
+                || contains(&[Keyword::FOR, Keyword::KEY]);
+            let into = keywords.iter().any(|(keyword, keyword_depth)| {
+                *keyword == Keyword::INTO && keyword_depth == depth
+            });
+            (StatementKind::Select, !locking && !into)
+        }
+        Keyword::INSERT => {
+            let upsert = contains(&[Keyword::ON, Keyword::DUPLICATE])
+                || contains(&[Keyword::DO, Keyword::UPDATE])
+                || contains(&[Keyword::OR, Keyword::REPLACE]);
+            if upsert {
+                (StatementKind::Upsert, false)
+            } else {
+                (StatementKind::Insert, false)
+            }
+        }
+        Keyword::REPLACE => (StatementKind::Upsert, false),
+        Keyword::UPDATE => (StatementKind::Update, false),
+        Keyword::DELETE => (StatementKind::Delete, false),
+        Keyword::MERGE => (StatementKind::Merge, false),
+        Keyword::CREATE
+        | Keyword::ALTER
+        | Keyword::DROP
+        | Keyword::TRUNCATE
+        | Keyword::RENAME
+        | Keyword::COMMENT => (StatementKind::Ddl, false),
+        Keyword::ROLLBACK if contains(&[Keyword::TO]) => (StatementKind::Savepoint, true),
+        Keyword::BEGIN
+        | Keyword::START
+        | Keyword::COMMIT
+        | Keyword::END
+        | Keyword::ROLLBACK
+        | Keyword::ABORT => (StatementKind::TransactionControl, true),
+        Keyword::SET if second == Some(Keyword::TRANSACTION) => {
+            (StatementKind::TransactionControl, true)
+        }
+        Keyword::SAVEPOINT | Keyword::RELEASE => (StatementKind::Savepoint, true),
+        Keyword::SET | Keyword::RESET | Keyword::USE | Keyword::DISCARD => {
+            (StatementKind::Session, true)
+        }
+        Keyword::SHOW => (StatementKind::Utility, true),
+        Keyword::EXPLAIN | Keyword::DESCRIBE | Keyword::DESC => {
+            (StatementKind::Utility, !contains(&[Keyword::ANALYZE]))
+        }
+        Keyword::ANALYZE
+        | Keyword::VACUUM
+        | Keyword::COPY
+        | Keyword::LOCK
+        | Keyword::PREPARE
+        | Keyword::EXECUTE
+        | Keyword::DEALLOCATE
+        | Keyword::DECLARE
+        | Keyword::FETCH
+        | Keyword::CLOSE
+        | Keyword::CALL
+        | Keyword::GRANT
+        | Keyword::REVOKE
+        | Keyword::KILL
+        | Keyword::PRAGMA => (StatementKind::Utility, false),
+        _ => (StatementKind::Unknown, false),
+    }
+}
+
+/// The names of the CTEs a query defines.
+fn cte_names(query: &Query) -> HashSet<String> {
+    query
+        .with
+        .iter()
+        .flat_map(|with| &with.cte_tables)
+        .map(|cte| cte.alias.name.value.clone())
+        .collect()
+}
+
+struct TableCollector<'a> {
+    options: &'a Fingerprinter<'a>,
+    /// The names of the CTEs in scope, per query being visited.
+    cte_scopes: Vec<HashSet<String>>,
+    /// Statement targets already recorded with a role, which are skipped when next visited as
+    /// relations.
+    pending_targets: Vec<ObjectName>,
+    /// Whether the next relation visited names a table-valued function, like `generate_series`.
+    in_table_function: bool,
+    tables: Vec<TableReference>,
+}
+
+impl TableCollector<'_> {
+    fn record(&mut self, name: &ObjectName, role: TableRole) {
+        if let [ObjectNamePart::Identifier(ident)] = name.0.as_slice()
+            && self
+                .cte_scopes
+                .iter()
+                .any(|scope| scope.contains(&ident.value))
+        {
+            return;
+        }
+        let mut name = name.clone();
+        self.options.normalize_relation_name(&mut name);
+        let table = TableReference::new(name.to_string(), role);
+        if !self.tables.contains(&table) {
+            self.tables.push(table);
+        }
+    }
+
+    /// Record a statement's target, which is then skipped when visited as a relation.
+    fn record_target(&mut self, name: &ObjectName, role: TableRole) {
+        self.pending_targets.push(name.clone());
+        self.record(name, role);
+    }
+
+    fn record_target_table_factor(&mut self, table_factor: &TableFactor, role: TableRole) {
+        if let TableFactor::Table {
+            name, args: None, ..
+        } = table_factor
+        {
+            self.record_target(name, role);
+        }
+    }
+
+    /// Record the tables locked by a `FOR UPDATE` or similar clause, resolving aliases in `OF`.
+    fn record_locked_tables(&mut self, select: &Select, of: Option<&ObjectName>) {
+        let relations = select.from.iter().flat_map(|table| {
+            iter::once(&table.relation).chain(table.joins.iter().map(|join| &join.relation))
+        });
+        let mut found = false;
+        for relation in relations {
+            let TableFactor::Table {
+                name,
+                alias,
+                args: None,
+                ..
+            } = relation
+            else {
+                continue;
+            };
+            let matches = match of {
+                Some(of) => {
+                    of == name
+                        || matches!(
+                            (of.0.as_slice(), alias),
+                            ([ObjectNamePart::Identifier(ident)], Some(alias))
+                                if ident.value == alias.name.value
+                        )
+                }
+                None => true,
+            };
+            if matches {
+                self.record(name, TableRole::LockTarget);
+                found = true;
+            }
+        }
+        if let Some(of) = of
+            && !found
+        {
+            self.record(of, TableRole::LockTarget);
+        }
+    }
+}
+
+impl Visitor for TableCollector<'_> {
+    type Break = ();
+
+    fn pre_visit_statement(&mut self, statement: &Statement) -> ControlFlow<Self::Break> {
+        match statement {
+            Statement::Insert(Insert {
+                table: TableObject::TableName(name),
+                ..
+            }) => {
+                self.record_target(name, TableRole::Write);
+            }
+            Statement::Update(Update { table, .. }) => {
+                self.record_target_table_factor(&table.relation, TableRole::Write);
+            }
+            Statement::Delete(Delete { tables, from, .. }) => {
+                if tables.is_empty() {
+                    let (FromTable::WithFromKeyword(from) | FromTable::WithoutKeyword(from)) = from;
+                    for table in from {
+                        self.record_target_table_factor(&table.relation, TableRole::Write);
+                    }
+                } else {
+                    for name in tables {
+                        self.record(name, TableRole::Write);
+                    }
+                }
+            }
+            Statement::Merge(Merge { table, .. }) => {
+                self.record_target_table_factor(table, TableRole::Write);
+            }
+            Statement::Truncate(Truncate { table_names, .. }) => {
+                for target in table_names {
+                    self.record_target(&target.name, TableRole::Write);
+                }
+            }
+            Statement::Copy {
+                source: CopySource::Table { table_name, .. },
+                to,
+                ..
+            } => {
+                let role = if *to {
+                    TableRole::Read
+                } else {
+                    TableRole::Write
+                };
+                self.record(table_name, role);
+            }
+            Statement::CreateTable(CreateTable { name, .. })
+            | Statement::CreateView(CreateView { name, .. })
+            | Statement::CreateVirtualTable { name, .. }
+            | Statement::AlterTable(AlterTable { name, .. })
+            | Statement::AlterView { name, .. }
+            | Statement::CreateIndex(CreateIndex {
+                table_name: name, ..
+            }) => {
+                self.record_target(name, TableRole::DdlTarget);
+            }
+            Statement::Drop {
+                object_type: ObjectType::Table | ObjectType::View | ObjectType::MaterializedView,
+                names,
+                ..
+            } => {
+                for name in names {
+                    self.record(name, TableRole::DdlTarget);
+                }
+            }
+            Statement::RenameTable(renames) => {
+                for rename in renames {
+                    self.record(&rename.old_name, TableRole::DdlTarget);
+                    self.record(&rename.new_name, TableRole::DdlTarget);
+                }
+            }
+            Statement::Lock(Lock { tables, .. }) => {
+                for target in tables {
+                    self.record_target(&target.name, TableRole::LockTarget);
+                }
+            }
+            Statement::LockTables { tables } => {
+                for table in tables {
+                    self.record(
+                        &ObjectName::from(vec![table.table.clone()]),
+                        TableRole::LockTarget,
+                    );
+                }
+            }
+            _ => {}
+        }
+        ControlFlow::Continue(())
+    }
+
+    fn pre_visit_query(&mut self, query: &Query) -> ControlFlow<Self::Break> {
+        self.cte_scopes.push(cte_names(query));
+        if let SetExpr::Select(select) = query.body.as_ref() {
+            for lock in &query.locks {
+                self.record_locked_tables(select, lock.of.as_ref());
+            }
+        }
+        ControlFlow::Continue(())
+    }
+
+    fn post_visit_query(&mut self, _query: &Query) -> ControlFlow<Self::Break> {
+        self.cte_scopes.pop();
+        ControlFlow::Continue(())
+    }
+
+    fn post_visit_statement(&mut self, _statement: &Statement) -> ControlFlow<Self::Break> {
+        // Not all targets are visited as relations, so don't let them skip later statements'.
+        self.pending_targets.clear();
+        ControlFlow::Continue(())
+    }
+
+    fn pre_visit_table_factor(&mut self, table_factor: &TableFactor) -> ControlFlow<Self::Break> {
+        // The name is visited next, as a relation.
+        self.in_table_function = matches!(table_factor, TableFactor::Table { args: Some(_), .. });
+        ControlFlow::Continue(())
+    }
+
+    fn pre_visit_relation(&mut self, relation: &ObjectName) -> ControlFlow<Self::Break> {
+        // Table-valued functions, like `generate_series(1, 10)`, aren't tables.
+        if std::mem::take(&mut self.in_table_function) {
+            return ControlFlow::Continue(());
+        }
+        if let Some(index) = self
+            .pending_targets
+            .iter()
+            .position(|target| target == relation)
+        {
+            self.pending_targets.swap_remove(index);
+        } else {
+            self.record(relation, TableRole::Read);
+        }
+        ControlFlow::Continue(())
+    }
+}
+
+fn collect_metrics(ast: &[Statement]) -> QueryMetrics {
+    let mut collector = MetricsCollector::default();
+    for stmt in ast {
+        let _ = stmt.visit(&mut collector);
+    }
+    collector.metrics
+}
+
+/// Count the queries directly within a node, not counting those nested in them.
+fn count_queries<V: Visit + ?Sized>(node: &V) -> usize {
+    struct QueryCounter {
+        depth: usize,
+        count: usize,
+    }
+
+    impl Visitor for QueryCounter {
+        type Break = ();
+
+        fn pre_visit_query(&mut self, _query: &Query) -> ControlFlow<Self::Break> {
+            if self.depth == 0 {
+                self.count += 1;
+            }
+            self.depth += 1;
+            ControlFlow::Continue(())
+        }
+
+        fn post_visit_query(&mut self, _query: &Query) -> ControlFlow<Self::Break> {
+            self.depth -= 1;
+            ControlFlow::Continue(())
+        }
+    }
+
+    let mut counter = QueryCounter { depth: 0, count: 0 };
+    let _ = node.visit(&mut counter);
+    counter.count
+}
+
+#[derive(Default)]
+struct MetricsCollector {
+    metrics: QueryMetrics,
+    /// Whether the next query visited is a statement's main query.
+    top_level_pending: bool,
+    /// The queries being visited, innermost last.
+    queries: Vec<QueryScope>,
+}
+
+/// A query being visited by [`MetricsCollector`], which recognizes its CTE definitions and set
+/// operation operands by their position among the queries directly within it.
+struct QueryScope {
+    depth: usize,
+    /// The number of CTE definitions not yet visited, which come before any other queries.
+    ctes_pending: usize,
+    /// The positions of parenthesized set operation operands, which don't add nesting, among
+    /// the queries directly within the body.
+    operands: Vec<usize>,
+    /// The number of queries directly within the body visited so far.
+    children: usize,
+}
+
+impl MetricsCollector {
+    /// Count a query body's metrics, recording the positions of its operands among the
+    /// queries directly within it, counted by `children`.
+    fn count_set_expr(
+        &mut self,
+        set_expr: &SetExpr,
+        depth: usize,
+        children: &mut usize,
+        operands: &mut Vec<usize>,
+    ) {
+        match set_expr {
+            SetExpr::SetOperation { left, right, .. } => {
+                self.metrics.set_operations += 1;
+                self.count_set_expr(left, depth, children, operands);
+                self.count_set_expr(right, depth, children, operands);
+            }
+            SetExpr::Query(_) => {
+                operands.push(*children);
+                *children += 1;
+            }
+            SetExpr::Select(select) => {
+                self.count_joins(&select.from);
+                if depth == 0 {
+                    self.metrics.projection_width =
+                        self.metrics.projection_width.max(select.projection.len());
+                }
+                *children += count_queries(select.as_ref());
+            }
+            set_expr => {
+                *children += count_queries(set_expr);
+            }
+        }
+    }
+
+    fn count_joins<'t>(&mut self, tables: impl IntoIterator<Item = &'t TableWithJoins>) {
+        let mut count = 0usize;
+        for table in tables {
+            self.metrics.joins += table.joins.len();
+            count += 1;
+        }
+        self.metrics.joins += count.saturating_sub(1);
+    }
+}
+
+impl Visitor for MetricsCollector {
+    type Break = ();
+
+    fn pre_visit_statement(&mut self, statement: &Statement) -> ControlFlow<Self::Break> {
+        match statement {
+            Statement::Query(_)
+            | Statement::CreateView(_)
+            | Statement::CreateTable(CreateTable { query: Some(_), .. })
+            | Statement::Insert(Insert {
+                source: Some(_), ..
+            }) => {
+                // The main query is visited first.
+                self.top_level_pending = true;
+            }
+            Statement::Update(Update { table, from, .. }) => {
+                let from = match from {
+                    Some(
+                        UpdateTableFromKind::BeforeSet(from) | UpdateTableFromKind::AfterSet(from),
+                    ) => from.as_slice(),
+                    None => &[],
+                };
+                self.count_joins(iter::once(table).chain(from));
+            }
+            Statement::Delete(Delete { from, using, .. }) => {
+                let (FromTable::WithFromKeyword(from) | FromTable::WithoutKeyword(from)) = from;
+                self.count_joins(from.iter().chain(using.iter().flatten()));
+            }
+            _ => {}
+        }
+        ControlFlow::Continue(())
+    }
+
+    fn pre_visit_query(&mut self, query: &Query) -> ControlFlow<Self::Break> {
+        let top_level = std::mem::take(&mut self.top_level_pending);
+        let parent = self.queries.last_mut();
+        let (is_cte, is_operand) = match parent {
+            Some(parent) if parent.ctes_pending > 0 => {
+                parent.ctes_pending -= 1;
+                (true, false)
+            }
+            Some(parent) => {
+                let is_operand = parent.operands.contains(&parent.children);
+                parent.children += 1;
+                (false, is_operand)
+            }
+            None => (false, false),
+        };
+        let parent = self.queries.last().map(|parent| parent.depth);
+        let depth = if top_level {
+            0
+        } else if is_operand {
+            parent.unwrap_or(0)
+        } else {
+            if !is_cte {
+                self.metrics.subqueries += 1;
+            }
+            // Queries outside a statement's main query, like in `UPDATE ... WHERE`, are nested
+            // in the statement.
+            parent.map_or(1, |depth| depth + 1)
+        };
+        self.metrics.max_nesting_depth = self.metrics.max_nesting_depth.max(depth);
+        let ctes_pending = query.with.as_ref().map_or(0, |with| with.cte_tables.len());
+        self.metrics.ctes += ctes_pending;
+        let mut children = 0;
+        let mut operands = vec![];
+        self.count_set_expr(&query.body, depth, &mut children, &mut operands);
+        self.queries.push(QueryScope {
+            depth,
+            ctes_pending,
+            operands,
+            children: 0,
+        });
+        ControlFlow::Continue(())
+    }
+
+    fn post_visit_query(&mut self, _query: &Query) -> ControlFlow<Self::Break> {
+        self.queries.pop();
+        ControlFlow::Continue(())
+    }
+
+    fn pre_visit_table_factor(&mut self, table_factor: &TableFactor) -> ControlFlow<Self::Break> {
+        if let TableFactor::NestedJoin {
+            table_with_joins, ..
+        } = table_factor
+        {
+            self.count_joins([table_with_joins.as_ref()]);
+        }
+        ControlFlow::Continue(())
+    }
+
+    fn pre_visit_expr(&mut self, expr: &Expr) -> ControlFlow<Self::Break> {
+        let is_predicate = matches!(
+            expr,
+            Expr::BinaryOp {
+                op: BinaryOperator::Eq
+                    | BinaryOperator::NotEq
+                    | BinaryOperator::Lt
+                    | BinaryOperator::LtEq
+                    | BinaryOperator::Gt
+                    | BinaryOperator::GtEq
+                    | BinaryOperator::Spaceship,
+                ..
+            } | Expr::IsNull(_)
+                | Expr::IsNotNull(_)
+                | Expr::IsTrue(_)
+                | Expr::IsNotTrue(_)
+                | Expr::IsFalse(_)
+                | Expr::IsNotFalse(_)
+                | Expr::IsUnknown(_)
+                | Expr::IsNotUnknown(_)
+                | Expr::IsDistinctFrom(..)
+                | Expr::IsNotDistinctFrom(..)
+                | Expr::InList { .. }
+                | Expr::InSubquery { .. }
+                | Expr::InUnnest { .. }
+                | Expr::Between { .. }
+                | Expr::Like { .. }
+                | Expr::ILike { .. }
+                | Expr::SimilarTo { .. }
+                | Expr::RLike { .. }
+                | Expr::AnyOp { .. }
+                | Expr::AllOp { .. }
+                | Expr::Exists { .. }
+        );
+        if is_predicate {
+            self.metrics.predicates += 1;
+        }
+        ControlFlow::Continue(())
+    }
+}
+
+/// Count values, including bind parameters, but not elided values.
+fn count_values(ast: &[Statement]) -> usize {
+    let mut counter = ValueCounter::default();
+    for stmt in ast {
+        let _ = stmt.visit(&mut counter);
+    }
+    counter.count
+}
+
+#[derive(Default)]
+struct ValueCounter {
+    count: usize,
+}
+
+impl Visitor for ValueCounter {
+    type Break = ();
+
+    fn pre_visit_value(&mut self, value: &ValueWithSpan) -> ControlFlow<Self::Break> {
+        if !matches!(&value.value, Value::Placeholder(placeholder) if placeholder == "...") {
+            self.count += 1;
+        }
+        ControlFlow::Continue(())
+    }
+}
+
+#[derive(Default)]
+struct TransactionTracker {
+    report: TransactionReport,
+    /// Whether the last transaction is still open.
+    open: bool,
+    /// The path of indexes to the innermost active savepoint, in the last transaction.
+    active: Vec<usize>,
+}
+
+impl TransactionTracker {
+    fn statement(&mut self, index: usize, statement: &Statement) {
+        match statement {
+            Statement::StartTransaction { .. } if !self.open => {
+                self.report.transactions.push(Transaction {
+                    start: index,
+                    end: None,
+                    rolled_back: false,
+                    savepoints: vec![],
+                });
+                self.open = true;
+                self.report.max_depth = self.report.max_depth.max(1);
+            }
+            Statement::Commit { .. }
+            | Statement::Rollback {
+                savepoint: None, ..
+            } if self.open => {
+                self.end_transaction();
+                if let Some(transaction) = self.report.transactions.last_mut() {
+                    transaction.end = Some(index);
+                    transaction.rolled_back = matches!(statement, Statement::Rollback { .. });
+                }
+            }
+            Statement::Savepoint { name } if self.open => {
+                let savepoint = Savepoint {
+                    name: name.value.clone(),
+                    start: index,
+                    released: None,
+                    rollbacks: vec![],
+                    savepoints: vec![],
+                };
+                let siblings = self.active_savepoints();
+                siblings.push(savepoint);
+                let position = siblings.len() - 1;
+                self.active.push(position);
+                self.report.max_depth = self.report.max_depth.max(self.active.len() + 1);
+            }
+            Statement::ReleaseSavepoint { name } => match self.find_active(&name.value) {
+                Some(depth) => {
+                    // Releasing a savepoint also releases those created after it.
+                    for depth in depth..self.active.len() {
+                        self.savepoint_at(depth).released = Some(index);
+                    }
+                    self.active.truncate(depth);
+                }
+                None => self.report.unknown_savepoints.push(index),
+            },
+            Statement::Rollback {
+                savepoint: Some(name),
+                ..
+            } => match self.find_active(&name.value) {
+                Some(depth) => {
+                    self.savepoint_at(depth).rollbacks.push(index);
+                    // Savepoints created after the target are discarded.
+                    self.active.truncate(depth + 1);
+                }
+                None => self.report.unknown_savepoints.push(index),
+            },
+            Statement::StartTransaction { .. }
+            | Statement::Commit { .. }
+            | Statement::Rollback { .. }
+            | Statement::Savepoint { .. } => {
+                self.report.unexpected_transaction_control.push(index);
+            }
+            _ => self.query(index),
+        }
+    }
+
+    fn query(&mut self, index: usize) {
+        if !self.open {
+            self.report.outside_transaction.push(index);
+        }
+    }
+
+    fn finish(mut self) -> TransactionReport {
+        if self.open {
+            self.end_transaction();
+        }
+        self.report
+    }
+
+    /// Close the last transaction, reporting its active savepoints as unreleased.
+    fn end_transaction(&mut self) {
+        for depth in 0..self.active.len() {
+            let start = self.savepoint_at(depth).start;
+            self.report.unreleased_savepoints.push(start);
+        }
+        self.active.clear();
+        self.open = false;
+    }
+
+    /// Find the depth of the innermost active savepoint with the given name.
+    fn find_active(&mut self, name: &str) -> Option<usize> {
+        if !self.open {
+            return None;
+        }
+        (0..self.active.len())
+            .rev()
+            .find(|&depth| self.savepoint_at(depth).name == name)
+    }
+
+    fn savepoint_at(&mut self, depth: usize) -> &mut Savepoint {
+        let transaction = self
+            .report
+            .transactions
+            .last_mut()
+            .expect("savepoints are only active in a transaction");
+        let mut savepoint = &mut transaction.savepoints[self.active[0]];
+        for &position in &self.active[1..=depth] {
+            savepoint = &mut savepoint.savepoints[position];
+        }
+        savepoint
+    }
+
+    /// Return the list that a new savepoint is added to: the innermost active savepoint's
+    /// children, or the transaction's top-level savepoints.
+    fn active_savepoints(&mut self) -> &mut Vec<Savepoint> {
+        match self.active.len() {
+            0 => {
+                &mut self
+                    .report
+                    .transactions
+                    .last_mut()
+                    .expect("savepoints are only created in a transaction")
+                    .savepoints
+            }
+            len => &mut self.savepoint_at(len - 1).savepoints,
+        }
+    }
+}
+
+struct ColumnCollector<'a> {
+    options: &'a Fingerprinter<'a>,
+    /// The names of the CTEs in scope, per query being collected.
+    cte_scopes: Vec<HashSet<String>>,
+    /// The relations of the queries being collected, innermost last.
+    scopes: Vec<ColumnScope>,
+    columns: Vec<ColumnReference>,
+}
+
+#[derive(Default)]
+struct ColumnScope {
+    relations: Vec<ScopeRelation>,
+    /// Output column aliases, which `GROUP BY`, `HAVING`, and `ORDER BY` can refer to.
+    output_aliases: Vec<Ident>,
+}
+
+struct ScopeRelation {
+    /// The alias, or name, that columns can be qualified with.
+    qualifier: ObjectName,
+    /// The normalized table name, if the relation is a table.
+    table: Option<String>,
+}
+
+impl ColumnCollector<'_> {
+    fn collect_statement(&mut self, statement: &Statement) {
+        match statement {
+            Statement::Query(query) => self.collect_query(query),
+            Statement::Update(Update {
+                table,
+                from,
+                selection,
+                assignments,
+                ..
+            }) => {
+                let mut tables = vec![table];
+                if let Some(
+                    UpdateTableFromKind::BeforeSet(from) | UpdateTableFromKind::AfterSet(from),
+                ) = from
+                {
+                    tables.extend(from);
+                }
+                self.push_scope(tables);
+                for assignment in assignments {
+                    self.collect_expr(&assignment.value, None);
+                }
+                self.collect_expr_option(selection.as_ref(), Some(ColumnClause::Where));
+                self.scopes.pop();
+            }
+            Statement::Delete(Delete {
+                from,
+                using,
+                selection,
+                ..
+            }) => {
+                let (FromTable::WithFromKeyword(from) | FromTable::WithoutKeyword(from)) = from;
+                self.push_scope(from.iter().chain(using.iter().flatten()).collect());
+                self.collect_expr_option(selection.as_ref(), Some(ColumnClause::Where));
+                self.scopes.pop();
+            }
+            _ => {
+                // Collect from any queries, such as in `INSERT ... SELECT`.
+                let _ = statement.visit(&mut ColumnExprCollector {
+                    collector: self,
+                    clause: None,
+                    depth: 0,
+                });
+            }
+        }
+    }
+
+    fn collect_query(&mut self, query: &Query) {
+        self.cte_scopes.push(cte_names(query));
+        if let Some(with) = &query.with {
+            for cte in &with.cte_tables {
+                self.collect_query(&cte.query);
+            }
+        }
+        match query.body.as_ref() {
+            SetExpr::Select(select) => {
+                self.collect_select(select);
+                // `ORDER BY` can refer to the select's tables, so collect it in the same scope.
+                self.collect_order_by(query);
+                self.scopes.pop();
+            }
+            body => {
+                self.collect_set_expr(body);
+                self.scopes.push(ColumnScope::default());
+                self.collect_order_by(query);
+                self.scopes.pop();
+            }
+        }
+        self.cte_scopes.pop();
+    }
+
+    fn is_cte(&self, name: &ObjectName) -> bool {
+        matches!(
+            name.0.as_slice(),
+            [ObjectNamePart::Identifier(ident)]
+                if self.cte_scopes.iter().any(|scope| scope.contains(&ident.value))
+        )
+    }
+
+    fn collect_set_expr(&mut self, set_expr: &SetExpr) {
+        match set_expr {
+            SetExpr::Select(select) => {
+                self.collect_select(select);
+                self.scopes.pop();
+            }
+            SetExpr::Query(query) => self.collect_query(query),
+            SetExpr::SetOperation { left, right, .. } => {
+                self.collect_set_expr(left);
+                self.collect_set_expr(right);
+            }
+            SetExpr::Insert(statement)
+            | SetExpr::Update(statement)
+            | SetExpr::Delete(statement)
+            | SetExpr::Merge(statement) => self.collect_statement(statement),
+            SetExpr::Values(_) | SetExpr::Table(_) => {}
+        }
+    }
+
+    /// Collect from a select, leaving its scope pushed.
+    fn collect_select(&mut self, select: &Select) {
+        self.push_scope(select.from.iter().collect());
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.output_aliases = select
+                .projection
+                .iter()
+                .flat_map(|item| match item {
+                    SelectItem::ExprWithAlias { alias, .. } => vec![alias.clone()],
+                    SelectItem::ExprWithAliases { aliases, .. } => aliases.clone(),
+                    _ => vec![],
+                })
+                .collect();
+        }
+        for item in &select.projection {
+            match item {
+                SelectItem::UnnamedExpr(expr)
+                | SelectItem::ExprWithAlias { expr, .. }
+                | SelectItem::ExprWithAliases { expr, .. } => {
+                    self.collect_expr(expr, Some(ColumnClause::Projection));
+                }
+                SelectItem::Wildcard(_) => {
+                    self.record(None, &Ident::new("*"), ColumnClause::Projection);
+                }
+                SelectItem::QualifiedWildcard(
+                    SelectItemQualifiedWildcardKind::ObjectName(qualifier),
+                    _,
+                ) => {
+                    self.record(Some(qualifier), &Ident::new("*"), ColumnClause::Projection);
+                }
+                SelectItem::QualifiedWildcard(SelectItemQualifiedWildcardKind::Expr(expr), _) => {
+                    self.collect_expr(expr, None);
+                }
+            }
+        }
+        for table in &select.from {
+            self.collect_joins(table);
+        }
+        self.collect_expr_option(select.selection.as_ref(), Some(ColumnClause::Where));
+        if let GroupByExpr::Expressions(expressions, _) = &select.group_by {
+            for expr in expressions {
+                self.collect_expr(expr, Some(ColumnClause::GroupBy));
+            }
+        }
+        self.collect_expr_option(select.having.as_ref(), Some(ColumnClause::Having));
+    }
+
+    fn collect_joins(&mut self, table: &TableWithJoins) {
+        if let TableFactor::NestedJoin {
+            table_with_joins, ..
+        } = &table.relation
+        {
+            self.collect_joins(table_with_joins);
+        }
+        for join in &table.joins {
+            if let TableFactor::NestedJoin {
+                table_with_joins, ..
+            } = &join.relation
+            {
+                self.collect_joins(table_with_joins);
+            }
+            match join_constraint(&join.join_operator) {
+                Some(JoinConstraint::On(expr)) => {
+                    self.collect_expr(expr, Some(ColumnClause::Join));
+                }
+                Some(JoinConstraint::Using(names)) => {
+                    for name in names {
+                        if let Some(ObjectNamePart::Identifier(ident)) = name.0.last() {
+                            // `USING` columns belong to both sides of the join.
+                            let mut ident = ident.clone();
+                            self.options.maybe_unquote_ident(&mut ident);
+                            self.push_column(None, ident.to_string(), ColumnClause::Join);
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn collect_order_by(&mut self, query: &Query) {
+        if let Some(OrderBy {
+            kind: OrderByKind::Expressions(expressions),
+            ..
+        }) = &query.order_by
+        {
+            for expr in expressions {
+                self.collect_expr(&expr.expr, Some(ColumnClause::OrderBy));
+            }
+        }
+    }
+
+    fn collect_expr(&mut self, expr: &Expr, clause: Option<ColumnClause>) {
+        let _ = expr.visit(&mut ColumnExprCollector {
+            collector: self,
+            clause,
+            depth: 0,
+        });
+    }
+
+    fn collect_expr_option(&mut self, expr: Option<&Expr>, clause: Option<ColumnClause>) {
+        if let Some(expr) = expr {
+            self.collect_expr(expr, clause);
+        }
+    }
+
+    /// Push a scope for the given tables, collecting from any derived tables first.
+    fn push_scope(&mut self, tables: Vec<&TableWithJoins>) {
+        let mut scope = ColumnScope::default();
+        for table in tables {
+            self.add_scope_relation(&mut scope, &table.relation);
+            for join in &table.joins {
+                self.add_scope_relation(&mut scope, &join.relation);
+            }
+        }
+        self.scopes.push(scope);
+    }
+
+    fn add_scope_relation(&mut self, scope: &mut ColumnScope, relation: &TableFactor) {
+        match relation {
+            TableFactor::Table {
+                name,
+                alias,
+                args: None,
+                ..
+            } => {
+                let table = (!self.is_cte(name)).then(|| {
+                    let mut name = name.clone();
+                    self.options.normalize_relation_name(&mut name);
+                    name.to_string()
+                });
+                let qualifier = match alias {
+                    Some(alias) => ObjectName::from(vec![alias.name.clone()]),
+                    None => name.clone(),
+                };
+                scope.relations.push(ScopeRelation { qualifier, table });
+            }
+            TableFactor::Derived {
+                subquery, alias, ..
+            } => {
+                self.collect_query(subquery);
+                if let Some(alias) = alias {
+                    scope.relations.push(ScopeRelation {
+                        qualifier: ObjectName::from(vec![alias.name.clone()]),
+                        table: None,
+                    });
+                }
+            }
+            TableFactor::NestedJoin {
+                table_with_joins, ..
+            } => {
+                self.add_scope_relation(scope, &table_with_joins.relation);
+                for join in &table_with_joins.joins {
+                    self.add_scope_relation(scope, &join.relation);
+                }
+            }
+            _ => {
+                let mut relation = relation.clone();
+                if let Some(alias) = table_factor_alias(&mut relation) {
+                    scope.relations.push(ScopeRelation {
+                        qualifier: ObjectName::from(vec![alias.name.clone()]),
+                        table: None,
+                    });
+                }
+            }
+        }
+    }
+
+    fn record_identifiers(&mut self, idents: &[Ident], clause: ColumnClause) {
+        let Some((column, qualifier)) = idents.split_last() else {
+            return;
+        };
+        if qualifier.is_empty() {
+            // Skip driver parameters and references to output aliases.
+            if column.value.starts_with('@')
+                || matches!(
+                    clause,
+                    ColumnClause::GroupBy | ColumnClause::Having | ColumnClause::OrderBy
+                ) && self.scopes.last().is_some_and(|scope| {
+                    scope
+                        .output_aliases
+                        .iter()
+                        .any(|alias| alias.value == column.value)
+                })
+            {
+                return;
+            }
+            self.record(None, column, clause);
+        } else {
+            self.record(Some(&ObjectName::from(qualifier.to_vec())), column, clause);
+        }
+    }
+
+    fn record(&mut self, qualifier: Option<&ObjectName>, column: &Ident, clause: ColumnClause) {
+        let table = self.resolve_table(qualifier);
+        let column = self.column_name(column);
+        self.push_column(table, column, clause);
+    }
+
+    fn resolve_table(&self, qualifier: Option<&ObjectName>) -> Option<String> {
+        match qualifier {
+            Some(qualifier) => self.resolve_qualifier(qualifier),
+            // Unqualified columns belong to the only table in their query, if there is one.
+            None => match self.scopes.last().map(|scope| scope.relations.as_slice()) {
+                Some([relation]) => relation.table.clone(),
+                _ => None,
+            },
+        }
+    }
+
+    fn column_name(&self, column: &Ident) -> String {
+        let mut column = column.clone();
+        self.options.maybe_unquote_ident(&mut column);
+        column.to_string()
+    }
+
+    /// Resolve an expression that is a plain column reference to its table, returning the
+    /// lineage from it to the target column.
+    fn column_lineage(&self, expr: &Expr, target: String) -> Option<(String, ColumnLineage)> {
+        let idents = match expr {
+            Expr::Identifier(ident) => std::slice::from_ref(ident),
+            Expr::CompoundIdentifier(idents) => idents.as_slice(),
+            _ => return None,
+        };
+        let (column, qualifier) = idents.split_last()?;
+        let qualifier = (!qualifier.is_empty()).then(|| ObjectName::from(qualifier.to_vec()));
+        let table = self.resolve_table(qualifier.as_ref())?;
+        Some((table, ColumnLineage::new(self.column_name(column), target)))
+    }
+
+    /// Collect the lineage of a query's projection into target columns, which are taken from
+    /// the projection's aliases and column names if not given.
+    fn collect_projection_lineage(
+        &mut self,
+        query: &Query,
+        targets: Option<Vec<Option<String>>>,
+    ) -> Vec<(String, ColumnLineage)> {
+        let SetExpr::Select(select) = query.body.as_ref() else {
+            return vec![];
+        };
+        // Wildcards make the projection's columns unknown.
+        if select.projection.iter().any(|item| {
+            matches!(
+                item,
+                SelectItem::Wildcard(_) | SelectItem::QualifiedWildcard(..)
+            )
+        }) || targets
+            .as_ref()
+            .is_some_and(|targets| targets.len() != select.projection.len())
+        {
+            return vec![];
+        }
+        self.cte_scopes.push(cte_names(query));
+        self.push_scope(select.from.iter().collect());
+        let mut lineage = vec![];
+        for (index, item) in select.projection.iter().enumerate() {
+            let (expr, alias) = match item {
+                SelectItem::UnnamedExpr(expr) => (expr, None),
+                SelectItem::ExprWithAlias { expr, alias } => (expr, Some(alias)),
+                _ => continue,
+            };
+            let target = match &targets {
+                Some(targets) => targets[index].clone(),
+                None => match (alias, expr) {
+                    (Some(alias), _) => Some(self.column_name(alias)),
+                    (None, Expr::Identifier(ident)) => Some(self.column_name(ident)),
+                    (None, Expr::CompoundIdentifier(idents)) => {
+                        idents.last().map(|ident| self.column_name(ident))
+                    }
+                    _ => None,
+                },
+            };
+            if let Some(target) = target {
+                lineage.extend(self.column_lineage(expr, target));
+            }
+        }
+        self.scopes.pop();
+        self.cte_scopes.pop();
+        lineage
+    }
+
+    /// Resolve a column qualifier to a table, searching from the innermost query outwards,
+    /// so correlated subqueries resolve too.
+    fn resolve_qualifier(&self, qualifier: &ObjectName) -> Option<String> {
+        for scope in self.scopes.iter().rev() {
+            let matching: Vec<_> = scope
+                .relations
+                .iter()
+                .filter(|relation| qualifier_matches(&relation.qualifier, qualifier))
+                .collect();
+            match matching.as_slice() {
+                [] => continue,
+                [relation] => return relation.table.clone(),
+                _ => return None,
+            }
+        }
+        // Not an alias, so presumably a table name.
+        let mut name = qualifier.clone();
+        self.options.normalize_relation_name(&mut name);
+        Some(name.to_string())
+    }
+
+    fn push_column(&mut self, table: Option<String>, name: String, clause: ColumnClause) {
+        let column = ColumnReference {
+            table,
+            name,
+            clause,
+        };
+        if !self.columns.contains(&column) {
+            self.columns.push(column);
+        }
+    }
+}
+
+/// Unwrap a data-modifying statement with a `WITH` clause, like `WITH c AS (...) INSERT ...`,
+/// which parses as a query.
+fn data_statement(statement: &Statement) -> &Statement {
+    if let Statement::Query(query) = statement
+        && let SetExpr::Insert(inner) | SetExpr::Merge(inner) = query.body.as_ref()
+    {
+        inner
+    } else {
+        statement
+    }
+}
+
+/// Check if a column qualifier refers to a relation, matching its trailing name parts.
+fn qualifier_matches(relation: &ObjectName, qualifier: &ObjectName) -> bool {
+    relation.0.len() >= qualifier.0.len()
+        && relation.0[relation.0.len() - qualifier.0.len()..]
+            .iter()
+            .zip(&qualifier.0)
+            .all(
+                |(relation_part, qualifier_part)| match (relation_part, qualifier_part) {
+                    (
+                        ObjectNamePart::Identifier(relation_ident),
+                        ObjectNamePart::Identifier(qualifier_ident),
+                    ) => ident_matches(qualifier_ident, &relation_ident.value),
+                    _ => false,
+                },
+            )
+}
+
+/// Collect the columns in an expression, handing any subqueries back to the
+/// [`ColumnCollector`] to collect in their own scope.
+struct ColumnExprCollector<'c, 'a> {
+    collector: &'c mut ColumnCollector<'a>,
+    clause: Option<ColumnClause>,
+    depth: usize,
+}
+
+impl Visitor for ColumnExprCollector<'_, '_> {
+    type Break = ();
+
+    fn pre_visit_query(&mut self, query: &Query) -> ControlFlow<Self::Break> {
+        if self.depth == 0 {
+            self.collector.collect_query(query);
+        }
+        self.depth += 1;
+        ControlFlow::Continue(())
+    }
+
+    fn post_visit_query(&mut self, _query: &Query) -> ControlFlow<Self::Break> {
+        self.depth -= 1;
+        ControlFlow::Continue(())
+    }
+
+    fn pre_visit_expr(&mut self, expr: &Expr) -> ControlFlow<Self::Break> {
+        if self.depth == 0
+            && let Some(clause) = self.clause
+        {
+            match expr {
+                Expr::Identifier(ident) => {
+                    self.collector
+                        .record_identifiers(std::slice::from_ref(ident), clause);
+                }
+                Expr::CompoundIdentifier(idents) => {
+                    self.collector.record_identifiers(idents, clause);
+                }
+                _ => {}
+            }
+        }
+        ControlFlow::Continue(())
+    }
+}
+
+fn join_constraint(join_operator: &JoinOperator) -> Option<&JoinConstraint> {
+    match join_operator {
+        JoinOperator::Join(constraint)
+        | JoinOperator::Inner(constraint)
+        | JoinOperator::Left(constraint)
+        | JoinOperator::LeftOuter(constraint)
+        | JoinOperator::Right(constraint)
+        | JoinOperator::RightOuter(constraint)
+        | JoinOperator::FullOuter(constraint)
+        | JoinOperator::Semi(constraint)
+        | JoinOperator::LeftSemi(constraint)
+        | JoinOperator::RightSemi(constraint)
+        | JoinOperator::Anti(constraint)
+        | JoinOperator::LeftAnti(constraint)
+        | JoinOperator::RightAnti(constraint) => Some(constraint),
+        _ => None,
+    }
+}
+
+/// Check for SQL Server-style driver parameters, like `@p1`, which sqlparser
+/// parses as identifiers.
+fn is_driver_parameter(ident: &Ident) -> bool {
+    ident.quote_style.is_none()
+        && ident
+            .value
+            .strip_prefix("@p")
+            .or_else(|| ident.value.strip_prefix("@P"))
+            .is_some_and(|number| !number.is_empty() && number.chars().all(|c| c.is_ascii_digit()))
+}
+
+/// Check if a query has nothing but a body, so parentheses around it are redundant.
+fn is_bare_query(query: &Query) -> bool {
+    let Query {
+        with,
+        body: _,
+        order_by,
+        limit_clause,
+        fetch,
+        locks,
+        for_clause,
+        settings,
+        format_clause,
+        pipe_operators,
+    } = query;
+    with.is_none()
+        && order_by.is_none()
+        && limit_clause.is_none()
+        && fetch.is_none()
+        && locks.is_empty()
+        && for_clause.is_none()
+        && settings.is_none()
+        && format_clause.is_none()
+        && pipe_operators.is_empty()
+}
+
+/// Remove parentheses around bare `SELECT` operands of set operations.
+/// Operands that are themselves set operations keep their parentheses, since they may be
+/// needed for precedence.
+fn remove_set_operand_parentheses(set_expr: &mut SetExpr) {
+    let mut stack = vec![set_expr];
+    while let Some(set_expr) = stack.pop() {
+        if let SetExpr::SetOperation { left, right, .. } = set_expr {
+            for operand in [left, right] {
+                if let SetExpr::Query(inner) = operand.as_mut()
+                    && is_bare_query(inner)
+                    && matches!(inner.body.as_ref(), SetExpr::Select(_))
+                {
+                    *operand = inner.body.clone();
+                }
+                stack.push(operand.as_mut());
+            }
+        }
+    }
+}
+
+/// Collapse consecutive identical branches of a chain of `UNION ALL` operations.
+fn collapse_union_all_branches(set_expr: &mut SetExpr) {
+    let mut branches = vec![];
+    let mut current = &*set_expr;
+    while let SetExpr::SetOperation {
+        left,
+        op: SetOperator::Union,
+        set_quantifier: SetQuantifier::All,
+        right,
+    } = current
+    {
+        branches.push(right.as_ref());
+        current = left.as_ref();
+    }
+    if branches.is_empty() {
+        return;
+    }
+    branches.push(current);
+    branches.reverse();
+    let original_len = branches.len();
+    branches.dedup_by_key(|branch| branch.to_string());
+    if branches.len() == original_len {
+        return;
+    }
+    let mut branches = branches.into_iter().cloned();
+    let first = branches.next().expect("at least one branch");
+    *set_expr = branches.fold(first, |left, right| SetExpr::SetOperation {
+        left: Box::new(left),
+        op: SetOperator::Union,
+        set_quantifier: SetQuantifier::All,
+        right: Box::new(right),
+    });
+}
+
+/// Sort the operands of `AND` and `OR` chains, the sides of equality comparisons, and the
+/// columns of tuple `IN` lists.
+fn sort_commutative_operands(expr: &mut Expr) {
+    match expr {
+        Expr::BinaryOp {
+            op: op @ (BinaryOperator::And | BinaryOperator::Or),
+            ..
+        } => {
+            let op = op.clone();
+            let mut operands = vec![];
+            flatten_binary_op(
+                std::mem::replace(expr, placeholder_value()),
+                &op,
+                &mut operands,
+            );
+            operands.sort_by_cached_key(|operand| operand.to_string());
+            let mut operands = operands.into_iter();
+            let first = operands.next().expect("at least two operands");
+            *expr = operands.fold(first, |left, right| Expr::BinaryOp {
+                left: Box::new(left),
+                op: op.clone(),
+                right: Box::new(right),
+            });
+        }
+        Expr::BinaryOp {
+            left,
+            op: BinaryOperator::Eq | BinaryOperator::NotEq,
+            right,
+        } => {
+            // Keep values on the right, as they are usually written.
+            let key = |expr: &Expr| (matches!(expr, Expr::Value(_)), expr.to_string());
+            if key(right) < key(left) {
+                std::mem::swap(left, right);
+            }
+        }
+        Expr::InList { expr, list, .. } => {
+            let Expr::Tuple(columns) = expr.as_mut() else {
+                return;
+            };
+            let all_tuples = list
+                .iter()
+                .all(|item| matches!(item, Expr::Tuple(values) if values.len() == columns.len()));
+            if !all_tuples {
+                return;
+            }
+            let mut order: Vec<usize> = (0..columns.len()).collect();
+            order.sort_by_cached_key(|&i| columns[i].to_string());
+            for item in list.iter_mut().chain([expr.as_mut()]) {
+                if let Expr::Tuple(values) = item {
+                    *values = order.iter().map(|&i| values[i].clone()).collect();
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+fn flatten_binary_op(expr: Expr, op: &BinaryOperator, operands: &mut Vec<Expr>) {
+    match expr {
+        Expr::BinaryOp {
+            left,
+            op: expr_op,
+            right,
+        } if expr_op == *op => {
+            flatten_binary_op(*left, op, operands);
+            flatten_binary_op(*right, op, operands);
+        }
+        expr => operands.push(expr),
+    }
+}
+
+/// Sort comma-separated tables in `FROM`, unless any of them might refer to another, like
+/// `LATERAL` subqueries and table-valued functions.
+fn sort_from_tables(from: &mut [TableWithJoins]) {
+    let independent = |relation: &TableFactor| {
+        matches!(
+            relation,
+            TableFactor::Table { args: None, .. } | TableFactor::Derived { lateral: false, .. }
+        )
+    };
+    let sortable = from.iter().all(|table| {
+        independent(&table.relation) && table.joins.iter().all(|join| independent(&join.relation))
+    });
+    if sortable {
+        from.sort_by_cached_key(|table| table.to_string());
+    }
+}
+
+fn canonicalize_join_operator(join_operator: &mut JoinOperator) {
+    let canonical = match join_operator {
+        JoinOperator::Inner(constraint) => JoinOperator::Join(constraint.clone()),
+        JoinOperator::LeftOuter(constraint) => JoinOperator::Left(constraint.clone()),
+        JoinOperator::RightOuter(constraint) => JoinOperator::Right(constraint.clone()),
+        _ => return,
+    };
+    *join_operator = canonical;
+}
+
+/// Rewrite `TOP n` and `FETCH FIRST n ROWS ONLY` to `LIMIT n`, unless they use `PERCENT` or
+/// `WITH TIES`, which `LIMIT` can't express.
+fn canonicalize_row_limit(query: &mut Query) {
+    let no_limit = matches!(
+        query.limit_clause,
+        None | Some(LimitClause::LimitOffset { limit: None, .. })
+    );
+    let limit = match (&mut query.fetch, query.body.as_mut()) {
+        (
+            fetch @ Some(Fetch {
+                with_ties: false,
+                percent: false,
+                quantity: Some(_),
+            }),
+            _,
+        ) if no_limit => fetch.take().and_then(|fetch| fetch.quantity),
+        (None, SetExpr::Select(select)) if query.limit_clause.is_none() => match &mut select.top {
+            top @ Some(Top {
+                with_ties: false,
+                percent: false,
+                quantity: Some(_),
+            }) => match top.take().and_then(|top| top.quantity) {
+                Some(TopQuantity::Expr(expr)) => Some(expr),
+                Some(TopQuantity::Constant(constant)) => {
+                    Some(Expr::value(Value::Number(constant.to_string(), false)))
+                }
+                None => None,
+            },
+            _ => None,
+        },
+        _ => None,
+    };
+    let Some(limit) = limit else {
+        return;
+    };
+    match &mut query.limit_clause {
+        Some(LimitClause::LimitOffset {
+            limit: limit_value,
+            offset,
+            ..
+        }) => {
+            *limit_value = Some(limit);
+            if let Some(offset) = offset {
+                offset.rows = OffsetRows::None;
+            }
+        }
+        _ => {
+            query.limit_clause = Some(LimitClause::LimitOffset {
+                limit: Some(limit),
+                offset: None,
+                limit_by: vec![],
+            });
+        }
+    }
+}
+
+fn canonicalize_set_quantifier(set_quantifier: &mut SetQuantifier) {
+    match set_quantifier {
+        SetQuantifier::Distinct => *set_quantifier = SetQuantifier::None,
+        SetQuantifier::DistinctByName => *set_quantifier = SetQuantifier::ByName,
+        _ => {}
+    }
+}
+
+fn placeholder_value() -> Expr {
+    Expr::Value(placeholder_value_with_span())
+}
+
+fn placeholder_value_with_span() -> ValueWithSpan {
+    ValueWithSpan {
+        value: Value::Placeholder("...".to_string()),
+        span: Span::empty(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlparser::dialect::{HiveDialect, SQLiteDialect};
+
+    #[test]
+    fn test_fingerprint_one() {
+        let result = fingerprint_one("SELECT 123", None);
+        assert_eq!(result, "SELECT ...");
+    }
+
+    #[test]
+    fn test_empty() {
+        let result = fingerprint_many(vec![""], None);
+        assert_eq!(result, vec![""]);
+    }
+
+    #[test]
+    fn test_unparsable() {
+        let result = fingerprint_many(vec!["SELECT  SELECT  SELECT  SELECT"], None);
+        assert_eq!(result, vec!["SELECT  SELECT  SELECT  SELECT"]);
+    }
+
+    #[test]
+    fn test_comments_dropped() {
+        let result = fingerprint_many(vec!["SELECT 123 /* magic value */"], None);
+        assert_eq!(result, vec!["SELECT ..."]);
+    }
+
+    #[test]
+    fn test_format_placeholders() {
+        let result = fingerprint_many(
+            vec![
+                "SELECT a FROM b WHERE c = %s LIMIT %s",
+                "SELECT * FROM f(%s, %(name)s)",
+            ],
+            None,
+        );
+        assert_eq!(
+            result,
+            vec![
+                "SELECT ... FROM b WHERE ... LIMIT ...",
+                "SELECT * FROM f(%s, %(name)s)"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_format_placeholders_modulo_unchanged() {
+        let result = fingerprint_many(vec!["SELECT * FROM f(a % s)"], None);
+        assert_eq!(result, vec!["SELECT * FROM f(a % s)"]);
+    }
+
+    #[test]
+    fn test_placeholder_style_unchanged_by_default() {
+        let result = fingerprint_many(vec!["SELECT * FROM f($1, ?, :name)"], None);
+        assert_eq!(result, vec!["SELECT * FROM f($1, ?, :name)"]);
+    }
+
+    #[test]
+    fn test_placeholder_style_question_mark() {
+        let fingerprinter = Fingerprinter::new().placeholder_style(PlaceholderStyle::QuestionMark);
+        let result = fingerprinter.fingerprint_many(vec![
+            "SELECT * FROM f($1, ?, ?12, :name, @p1, %s, %(name)s)",
+        ]);
+        assert_eq!(result, vec!["SELECT * FROM f(?, ?, ?, ?, ?, ?, ?)"]);
+    }
+
+    #[test]
+    fn test_placeholder_style_dollar_numbered() {
+        let fingerprinter =
+            Fingerprinter::new().placeholder_style(PlaceholderStyle::DollarNumbered);
+        let result = fingerprinter.fingerprint_many(vec![
+            "SELECT * FROM f(?, ?)",
+            "SELECT * FROM f(%(a)s) LIMIT %(b)s",
+        ]);
+        assert_eq!(
+            result,
+            vec!["SELECT * FROM f($1, $2)", "SELECT * FROM f($1) LIMIT ..."]
+        );
+    }
+
+    #[test]
+    fn test_placeholder_style_colon_numbered() {
+        let fingerprinter = Fingerprinter::new().placeholder_style(PlaceholderStyle::ColonNumbered);
+        let result = fingerprinter.fingerprint_one("SELECT * FROM f(:a, :b)");
+        assert_eq!(result, "SELECT * FROM f(:1, :2)");
+    }
+
+    #[test]
+    fn test_placeholder_style_format() {
+        let fingerprinter = Fingerprinter::new().placeholder_style(PlaceholderStyle::Format);
+        let result = fingerprinter.fingerprint_one("SELECT * FROM f($1, $2)");
+        assert_eq!(result, "SELECT * FROM f(%s, %s)");
+    }
+
+    #[test]
+    fn test_placeholder_style_reused_parameters() {
+        let fingerprinter =
+            Fingerprinter::new().placeholder_style(PlaceholderStyle::DollarNumbered);
+        let result = fingerprinter.fingerprint_many(vec![
+            "SELECT * FROM f($1, $1, $2)",
+            "SELECT * FROM f(:a, :b, :a)",
+            "SELECT * FROM f(?, ?)",
+        ]);
+        assert_eq!(
+            result,
+            vec![
+                "SELECT * FROM f($1, $1, $2)",
+                "SELECT * FROM f($1, $2, $1)",
+                "SELECT * FROM f($1, $2)",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_placeholder_style_mssql_driver_parameters() {
+        let fingerprinter = Fingerprinter::new()
+            .dialect(&MsSqlDialect {})
+            .placeholder_style(PlaceholderStyle::QuestionMark);
+        let result = fingerprinter.fingerprint_one("SELECT * FROM f(@p1, @P2, @total)");
+        assert_eq!(result, "SELECT * FROM f(?, ?, @total)");
+    }
+
+    fn sharded_fingerprinter() -> Fingerprinter<'static> {
+        Fingerprinter::new().rewrite_relation_names(|name| {
+            let suffix = name.strip_prefix("tenant_")?;
+            suffix
+                .chars()
+                .all(|c| c.is_ascii_digit())
+                .then(|| "tenant_*".to_string())
+        })
+    }
+
+    #[test]
+    fn test_rewrite_relation_names_select() {
+        let result = sharded_fingerprinter().fingerprint_many(vec![
+            "SELECT a FROM tenant_1.orders JOIN \"tenant_1\".\"items\" ON a = b",
+            "SELECT a FROM tenant_2.orders JOIN tenant_2.items ON a = b",
+        ]);
+        assert_eq!(
+            result,
+            vec!["SELECT ... FROM tenant_*.orders JOIN tenant_*.items ON ..."; 2]
+        );
+    }
+
+    #[test]
+    fn test_rewrite_relation_names_column_qualifiers() {
+        let result = sharded_fingerprinter().fingerprint_one(
+            "SELECT * FROM tenant_1.orders JOIN f(tenant_1.orders.tenant_2, tenant_3) AS g ON true",
+        );
+        assert_eq!(
+            result,
+            "SELECT * FROM tenant_*.orders JOIN f(tenant_*.orders.tenant_2, tenant_3) AS g ON ..."
+        );
+    }
+
+    #[test]
+    fn test_rewrite_relation_names_dml() {
+        let result = sharded_fingerprinter().fingerprint_many(vec![
+            "INSERT INTO tenant_1.orders (a) VALUES (1)",
+            "UPDATE tenant_1.orders SET a = 1",
+            "DELETE FROM tenant_1.orders",
+        ]);
+        assert_eq!(
+            result,
+            vec![
+                "INSERT INTO tenant_*.orders (...) VALUES (...)",
+                "UPDATE tenant_*.orders SET ... = ...",
+                "DELETE FROM tenant_*.orders",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_rewrite_relation_names_ddl() {
+        let result = sharded_fingerprinter().fingerprint_many(vec![
+            "CREATE TABLE tenant_1.orders (a INT)",
+            "CREATE INDEX i ON tenant_1.orders (a)",
+            "DROP TABLE tenant_1.orders, tenant_2.orders",
+        ]);
+        assert_eq!(
+            result,
+            vec![
+                "CREATE TABLE tenant_*.orders (a INT)",
+                "CREATE INDEX i ON tenant_*.orders(a)",
+                "DROP TABLE tenant_*.orders, tenant_*.orders",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_rewrite_relation_names_mysql_multi_table_delete() {
+        let result = sharded_fingerprinter()
+            .dialect(&MySqlDialect {})
+            .fingerprint_one("DELETE tenant_1.a FROM tenant_1.a JOIN b ON a.x = b.x");
+        assert_eq!(result, "DELETE tenant_*.a FROM tenant_*.a JOIN b ON ...");
+    }
+
+    #[test]
+    fn test_rewrite_relation_names_first_rule_wins() {
+        let fingerprinter = Fingerprinter::new()
+            .rewrite_relation_names(|name| name.starts_with("tmp_").then(|| "tmp_*".to_string()))
+            .rewrite_relation_names(|_| Some("other".to_string()));
+        let result = fingerprinter.fingerprint_one("SELECT * FROM tmp_8f3a2c");
+        assert_eq!(result, "SELECT * FROM tmp_*");
+    }
+
+    #[test]
+    fn test_schema_qualification_strip_default() {
+        let fingerprinter = Fingerprinter::new()
+            .schema_qualification(SchemaQualification::StripDefault("public".to_string()));
+        let result = fingerprinter.fingerprint_many(vec![
+            "SELECT * FROM users",
+            "SELECT * FROM PUBLIC.users",
+            "SELECT * FROM \"mydb\".\"public\".\"users\"",
+            "SELECT * FROM other.users",
+        ]);
+        assert_eq!(
+            result,
+            vec![
+                "SELECT * FROM users",
+                "SELECT * FROM users",
+                "SELECT * FROM users",
+                "SELECT * FROM other.users",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_schema_qualification_strip_default_quoted_case_sensitive() {
+        let fingerprinter = Fingerprinter::new()
+            .schema_qualification(SchemaQualification::StripDefault("public".to_string()));
+        let result = fingerprinter.fingerprint_one("SELECT * FROM \"PUBLIC\".users");
+        assert_eq!(result, "SELECT * FROM PUBLIC.users");
+    }
+
+    #[test]
+    fn test_schema_qualification_strip_all() {
+        let fingerprinter =
+            Fingerprinter::new().schema_qualification(SchemaQualification::StripAll);
+        let result = fingerprinter.fingerprint_many(vec![
+            "SELECT * FROM a.b.c JOIN d.e ON x = y",
+            "INSERT INTO a.b (c) VALUES (1)",
+            "DROP TABLE a.b",
+        ]);
+        assert_eq!(
+            result,
+            vec![
+                "SELECT * FROM c JOIN e ON ...",
+                "INSERT INTO b (...) VALUES (...)",
+                "DROP TABLE b",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_schema_qualification_qualify_with() {
+        let fingerprinter = Fingerprinter::new()
+            .schema_qualification(SchemaQualification::QualifyWith("public".to_string()));
+        let result = fingerprinter.fingerprint_many(vec![
+            "SELECT * FROM users",
+            "SELECT * FROM public.users",
+            "SELECT * FROM mydb.public.users",
+            "SELECT * FROM other.users",
+        ]);
+        assert_eq!(
+            result,
+            vec![
+                "SELECT * FROM public.users",
+                "SELECT * FROM public.users",
+                "SELECT * FROM public.users",
+                "SELECT * FROM other.users",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_schema_qualification_qualify_with_cte() {
+        let fingerprinter = Fingerprinter::new()
+            .schema_qualification(SchemaQualification::QualifyWith("public".to_string()));
+        let result = fingerprinter.fingerprint_one(
+            "WITH x AS (SELECT * FROM users) SELECT * FROM x JOIN (SELECT * FROM x) AS y ON true",
+        );
+        assert_eq!(
+            result,
+            "WITH x AS (SELECT * FROM public.users) SELECT * FROM x JOIN (SELECT * FROM x) AS y ON ..."
+        );
+    }
+
+    #[test]
+    fn test_schema_qualification_qualify_with_table_function() {
+        let fingerprinter = Fingerprinter::new()
+            .schema_qualification(SchemaQualification::QualifyWith("public".to_string()));
+        let result = fingerprinter
+            .fingerprint_one("SELECT * FROM users JOIN generate_series(1, 3) AS g ON true");
+        assert_eq!(
+            result,
+            "SELECT * FROM public.users JOIN generate_series(1, 3) AS g ON ..."
+        );
+    }
+
+    #[test]
+    fn test_schema_qualification_compound_identifier() {
+        let fingerprinter = Fingerprinter::new()
+            .schema_qualification(SchemaQualification::StripDefault("public".to_string()));
+        let result = fingerprinter.fingerprint_many(vec![
+            "SELECT * FROM f(\"public\".\"users\".\"id\", users.id, id)",
+            "SELECT * FROM f(mydb.public.users.id, other.users.id)",
+        ]);
+        assert_eq!(
+            result,
+            vec![
+                "SELECT * FROM f(users.id, users.id, id)",
+                "SELECT * FROM f(users.id, other.users.id)",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_anonymize_aliases_disabled_by_default() {
+        let result = fingerprint_one("SELECT U0.* FROM users U0", None);
+        assert_eq!(result, "SELECT U0.* FROM users U0");
+    }
+
+    #[test]
+    fn test_anonymize_aliases_table_aliases() {
+        let fingerprinter = Fingerprinter::new().anonymize_aliases(true);
+        let result = fingerprinter.fingerprint_many(vec![
+            "SELECT \"U0\".* FROM \"users\" U0 JOIN items AS T3 ON T3.x = U0.x",
+            "SELECT \"U1\".* FROM \"users\" U1 JOIN items AS T2 ON T2.x = U1.x",
+        ]);
+        assert_eq!(
+            result,
+            vec!["SELECT t1.* FROM users t1 JOIN items AS t2 ON ..."; 2]
+        );
+    }
+
+    #[test]
+    fn test_anonymize_aliases_ctes_and_derived_tables() {
+        let fingerprinter = Fingerprinter::new().anonymize_aliases(true);
+        let result = fingerprinter.fingerprint_one(
+            "WITH anon_1 AS (SELECT * FROM a AS users_1) SELECT anon_1.* FROM anon_1 JOIN (SELECT * FROM b AS V1) AS anon_2 ON true",
+        );
+        assert_eq!(
+            result,
+            "WITH t1 AS (SELECT * FROM a AS t2) SELECT t1.* FROM t1 JOIN (SELECT * FROM b AS t3) AS t4 ON ..."
+        );
+    }
+
+    #[test]
+    fn test_anonymize_aliases_references() {
+        let fingerprinter = Fingerprinter::new().anonymize_aliases(true);
+        let result = fingerprinter.fingerprint_one("SELECT * FROM f(U0.id, users.id) AS U0");
+        assert_eq!(result, "SELECT * FROM f(t1.id, users.id) AS t1");
+    }
+
+    #[test]
+    fn test_anonymize_aliases_skip_relation_names() {
+        let fingerprinter = Fingerprinter::new().anonymize_aliases(true);
+        let result = fingerprinter.fingerprint_one("SELECT * FROM t1 JOIN users u ON t1.id = u.id");
+        assert_eq!(result, "SELECT * FROM t1 JOIN users t2 ON ...");
+    }
+
+    #[test]
+    fn test_anonymize_aliases_numbered_per_string() {
+        let fingerprinter = Fingerprinter::new().anonymize_aliases(true);
+        let result = fingerprinter.fingerprint_many(vec![
+            "SELECT * FROM a AS x JOIN b AS y ON true",
+            "SELECT * FROM b AS y",
+        ]);
+        assert_eq!(
+            result,
+            vec![
+                "SELECT * FROM a AS t1 JOIN b AS t2 ON ...",
+                "SELECT * FROM b AS t1"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_postgres_unquote_lowercase_only() {
+        let result = fingerprint_one(
+            "SELECT * FROM \"MyTable\" JOIN \"other\" ON true",
+            Some(&PostgreSqlDialect {}),
+        );
+        assert_eq!(result, "SELECT * FROM \"MyTable\" JOIN other ON ...");
+    }
+
+    #[test]
+    fn test_snowflake_unquote_uppercase_only() {
+        let result = fingerprint_one(
+            "SELECT * FROM \"MyTable\" JOIN \"OTHER\" ON true JOIN \"lower\" ON true",
+            Some(&SnowflakeDialect {}),
+        );
+        assert_eq!(
+            result,
+            "SELECT * FROM \"MyTable\" JOIN OTHER ON ... JOIN \"lower\" ON ..."
+        );
+    }
+
+    #[test]
+    fn test_identifier_case_override() {
+        let fingerprinter = Fingerprinter::new().identifier_case(IdentifierCase::FoldLower);
+        let result =
+            fingerprinter.fingerprint_one("SELECT * FROM \"MyTable\" JOIN \"other\" ON true");
+        assert_eq!(result, "SELECT * FROM \"MyTable\" JOIN other ON ...");
+    }
+
+    #[test]
+    fn test_lowercase_identifiers() {
+        let fingerprinter = Fingerprinter::new()
+            .dialect(&PostgreSqlDialect {})
+            .lowercase_identifiers(true);
+        let result = fingerprinter.fingerprint_many(vec![
+            "SELECT * FROM Users",
+            "SELECT * FROM USERS",
+            "SELECT * FROM \"users\"",
+            "SELECT * FROM \"Users\"",
+        ]);
+        assert_eq!(
+            result,
+            vec![
+                "SELECT * FROM users",
+                "SELECT * FROM users",
+                "SELECT * FROM users",
+                "SELECT * FROM \"Users\"",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_lowercase_identifiers_case_sensitive() {
+        let fingerprinter = Fingerprinter::new()
+            .dialect(&ClickHouseDialect {})
+            .lowercase_identifiers(true);
+        let result = fingerprinter.fingerprint_one("SELECT * FROM Users");
+        assert_eq!(result, "SELECT * FROM Users");
+    }
+
+    #[test]
+    fn test_unquote_non_ascii_identifiers() {
+        let result = fingerprint_one("SELECT * FROM \"fromage_é\"", None);
+        assert_eq!(result, "SELECT * FROM fromage_é");
+    }
+
+    #[test]
+    fn test_unquote_non_ascii_identifiers_disabled() {
+        let fingerprinter = Fingerprinter::new().unquote_non_ascii_identifiers(false);
+        let result = fingerprinter.fingerprint_one("SELECT * FROM \"fromage_é\"");
+        assert_eq!(result, "SELECT * FROM \"fromage_é\"");
+    }
+
+    #[test]
+    fn test_reserved_keywords_stay_quoted() {
+        let result = fingerprint_one(
+            "SELECT * FROM \"user\" JOIN \"order\" ON true JOIN \"users\" ON true",
+            None,
+        );
+        assert_eq!(
+            result,
+            "SELECT * FROM \"user\" JOIN \"order\" ON ... JOIN users ON ..."
+        );
+    }
+
+    #[test]
+    fn test_reserved_keywords_dialect_specific() {
+        let result = fingerprint_many(vec!["SELECT * FROM \"interval\""], None);
+        assert_eq!(result, vec!["SELECT * FROM \"interval\""]);
+        let result = fingerprint_many(
+            vec!["SELECT * FROM \"interval\""],
+            Some(&PostgreSqlDialect {}),
+        );
+        assert_eq!(result, vec!["SELECT * FROM interval"]);
+    }
+
+    #[test]
+    fn test_non_reserved_keywords_unquoted() {
+        let dialects: [&dyn Dialect; 3] =
+            [&PostgreSqlDialect {}, &MySqlDialect {}, &SQLiteDialect {}];
+        for dialect in dialects {
+            let result = fingerprint_one(
+                "SELECT * FROM \"format\" JOIN \"start\" ON true JOIN \"partition\" ON true \
+                 JOIN \"settings\" ON true JOIN \"open\" ON true JOIN \"global\" ON true \
+                 JOIN \"sample\" ON true",
+                Some(dialect),
+            );
+            assert_eq!(
+                result,
+                "SELECT * FROM format JOIN start ON ... JOIN partition ON ... JOIN settings ON ... \
+                 JOIN open ON ... JOIN global ON ... JOIN sample ON ...",
+                "{dialect:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_reserved_keywords_mysql() {
+        let result = fingerprint_one(
+            "SELECT * FROM `key` JOIN `start` ON true",
+            Some(&MySqlDialect {}),
+        );
+        assert_eq!(result, "SELECT * FROM `key` JOIN start ON ...");
+    }
+
+    #[test]
+    fn test_leading_digit_stays_quoted() {
+        let result = fingerprint_one("SELECT * FROM \"1abc\"", None);
+        assert_eq!(result, "SELECT * FROM \"1abc\"");
+    }
+
+    #[test]
+    fn test_canonicalize_syntax_joins() {
+        let fingerprinter = Fingerprinter::new().canonicalize_syntax(true);
+        let result = fingerprinter.fingerprint_many(vec![
+            "SELECT a FROM b INNER JOIN c ON b.id = c.id LEFT OUTER JOIN d ON true RIGHT OUTER JOIN e ON true",
+            "SELECT a FROM b JOIN c ON b.id = c.id LEFT JOIN d ON true RIGHT JOIN e ON true",
+        ]);
+        assert_eq!(
+            result,
+            vec!["SELECT ... FROM b JOIN c ON ... LEFT JOIN d ON ... RIGHT JOIN e ON ..."; 2]
+        );
+    }
+
+    #[test]
+    fn test_canonicalize_syntax_not_equal() {
+        let fingerprinter = Fingerprinter::new().canonicalize_syntax(true);
+        let result = fingerprinter.fingerprint_one("SELECT * FROM f(a != b)");
+        assert_eq!(result, "SELECT * FROM f(a <> b)");
+    }
+
+    #[test]
+    fn test_canonicalize_syntax_begin() {
+        let fingerprinter = Fingerprinter::new().canonicalize_syntax(true);
+        let result = fingerprinter.fingerprint_many(vec![
+            "START TRANSACTION",
+            "BEGIN TRANSACTION",
+            "BEGIN WORK",
+            "BEGIN",
+            "START TRANSACTION ISOLATION LEVEL SERIALIZABLE",
+        ]);
+        assert_eq!(
+            result,
+            vec![
+                "BEGIN",
+                "BEGIN",
+                "BEGIN",
+                "BEGIN",
+                "BEGIN ISOLATION LEVEL SERIALIZABLE",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_canonicalize_syntax_commit() {
+        let fingerprinter = Fingerprinter::new()
+            .dialect(&PostgreSqlDialect {})
+            .canonicalize_syntax(true);
+        let result = fingerprinter.fingerprint_many(vec!["END", "COMMIT WORK"]);
+        assert_eq!(result, vec!["COMMIT", "COMMIT"]);
+    }
+
+    #[test]
+    fn test_canonicalize_syntax_union_distinct() {
+        let fingerprinter = Fingerprinter::new().canonicalize_syntax(true);
+        let result = fingerprinter.fingerprint_many(vec![
+            "SELECT a FROM b UNION DISTINCT SELECT a FROM c UNION DISTINCT SELECT a FROM d",
+            "SELECT a FROM b UNION ALL SELECT a FROM c",
+        ]);
+        assert_eq!(
+            result,
+            vec![
+                "SELECT ... FROM b UNION SELECT ... FROM c UNION SELECT ... FROM d",
+                "SELECT ... FROM b UNION ALL SELECT ... FROM c",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_canonicalize_syntax_table_alias_as() {
+        let fingerprinter = Fingerprinter::new().canonicalize_syntax(true);
+        let result = fingerprinter.fingerprint_many(vec![
+            "SELECT * FROM a x, (SELECT 1) y",
+            "SELECT * FROM a AS x, (SELECT 1) AS y",
+        ]);
+        assert_eq!(result, vec!["SELECT * FROM a AS x, (SELECT ...) AS y"; 2]);
+    }
+
+    #[test]
+    fn test_canonicalize_syntax_row_limit() {
+        let mssql = Fingerprinter::new()
+            .dialect(&MsSqlDialect {})
+            .canonicalize_syntax(true);
+        let postgres = Fingerprinter::new()
+            .dialect(&PostgreSqlDialect {})
+            .canonicalize_syntax(true);
+        let results = vec![
+            mssql.fingerprint_one("SELECT TOP 10 * FROM t ORDER BY a"),
+            mssql.fingerprint_one("SELECT TOP (10) * FROM t ORDER BY a"),
+            postgres.fingerprint_one("SELECT * FROM t ORDER BY a LIMIT 10"),
+            postgres.fingerprint_one("SELECT * FROM t ORDER BY a FETCH FIRST 10 ROWS ONLY"),
+        ];
+        assert_eq!(results, vec!["SELECT * FROM t ORDER BY ... LIMIT ..."; 4]);
+        let results = vec![
+            postgres.fingerprint_one("SELECT * FROM t OFFSET 5 ROWS FETCH NEXT 10 ROWS ONLY"),
+            postgres.fingerprint_one("SELECT * FROM t LIMIT 10 OFFSET 5"),
+        ];
+        assert_eq!(results, vec!["SELECT * FROM t LIMIT ... OFFSET ..."; 2]);
+    }
+
+    #[test]
+    fn test_canonicalize_syntax_row_limit_kept() {
+        let result = Fingerprinter::new()
+            .dialect(&MsSqlDialect {})
+            .canonicalize_syntax(true)
+            .fingerprint_one("SELECT TOP 10 PERCENT * FROM t");
+        let fingerprinter = Fingerprinter::new().canonicalize_syntax(true);
+        assert_eq!(result, "SELECT TOP (...) PERCENT * FROM t");
+        let result = fingerprinter.fingerprint_one("SELECT * FROM t FETCH FIRST 10 ROWS WITH TIES");
+        assert_eq!(result, "SELECT * FROM t FETCH FIRST ... ROWS WITH TIES");
+    }
+
+    #[test]
+    fn test_canonicalize_syntax_table_alias_oracle() {
+        let fingerprinter = Fingerprinter::new()
+            .dialect(&OracleDialect {})
+            .canonicalize_syntax(true);
+        let result = fingerprinter.fingerprint_many(vec![
+            "SELECT * FROM a x, (SELECT 1 FROM dual) y",
+            "SELECT * FROM a AS x, (SELECT 1 FROM dual) AS y",
+        ]);
+        assert_eq!(
+            result,
+            vec!["SELECT * FROM a x, (SELECT ... FROM dual) y"; 2]
+        );
+    }
+
+    #[test]
+    fn test_canonicalize_syntax_disabled_by_default() {
+        let result = fingerprint_many(
+            vec![
+                "SELECT * FROM a x INNER JOIN b ON true",
+                "START TRANSACTION",
+            ],
+            None,
+        );
+        assert_eq!(
+            result,
+            vec!["SELECT * FROM a x INNER JOIN b ON ...", "START TRANSACTION"]
+        );
+    }
+
+    #[test]
+    fn test_select_nested_join_on() {
+        let result = fingerprint_one("SELECT * FROM (a JOIN b ON (a.x = b.x))", None);
+        assert_eq!(result, "SELECT * FROM (a JOIN b ON ...)");
+    }
+
+    #[test]
+    fn test_remove_redundant_parentheses_set_operations() {
+        let fingerprinter = Fingerprinter::new().remove_redundant_parentheses(true);
+        let result = fingerprinter.fingerprint_many(vec![
+            "(SELECT a, b FROM c) UNION (SELECT a, b FROM d)",
+            "SELECT a, b FROM c UNION SELECT a, b FROM d",
+        ]);
+        assert_eq!(result, vec!["SELECT ... FROM c UNION SELECT ... FROM d"; 2]);
+    }
+
+    #[test]
+    fn test_remove_redundant_parentheses_set_operations_kept() {
+        let fingerprinter = Fingerprinter::new().remove_redundant_parentheses(true);
+        let result = fingerprinter.fingerprint_many(vec![
+            "(SELECT a FROM b LIMIT 1) UNION (SELECT a FROM c)",
+            "SELECT a FROM b EXCEPT (SELECT a FROM c UNION SELECT a FROM d)",
+        ]);
+        assert_eq!(
+            result,
+            vec![
+                "(SELECT ... FROM b LIMIT ...) UNION SELECT ... FROM c",
+                "SELECT ... FROM b EXCEPT (SELECT ... FROM c UNION SELECT ... FROM d)",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_remove_redundant_parentheses_query() {
+        let fingerprinter = Fingerprinter::new().remove_redundant_parentheses(true);
+        let result = fingerprinter.fingerprint_many(vec![
+            "((SELECT a FROM b))",
+            "((SELECT a FROM b) ORDER BY a)",
+        ]);
+        assert_eq!(
+            result,
+            vec!["SELECT ... FROM b", "SELECT ... FROM b ORDER BY ..."]
+        );
+    }
+
+    #[test]
+    fn test_remove_redundant_parentheses_expressions() {
+        let fingerprinter = Fingerprinter::new().remove_redundant_parentheses(true);
+        let result = fingerprinter.fingerprint_one("SELECT * FROM f((a), ((1)), (g(b)), (a + b))");
+        assert_eq!(result, "SELECT * FROM f(a, 1, g(b), (a + b))");
+    }
+
+    #[test]
+    fn test_remove_redundant_parentheses_field_access() {
+        let fingerprinter = Fingerprinter::new()
+            .dialect(&PostgreSqlDialect {})
+            .remove_redundant_parentheses(true);
+        let result = fingerprinter.fingerprint_one("SELECT * FROM f((g(x)).y, (a).b, ((a)).b)");
+        assert_eq!(result, "SELECT * FROM f((g(x)).y, (a).b, (a).b)");
+    }
+
+    #[test]
+    fn test_collapse_union_all() {
+        let fingerprinter = Fingerprinter::new().collapse_union_all(true);
+        let result = fingerprinter.fingerprint_many(vec![
+            "SELECT a FROM b WHERE c = 1",
+            "SELECT a FROM b WHERE c = 1 UNION ALL SELECT a FROM b WHERE c = 2",
+            "SELECT a FROM b WHERE c = 1 UNION ALL SELECT a FROM b WHERE c = 2 UNION ALL SELECT a FROM b WHERE c = 3",
+        ]);
+        assert_eq!(result, vec!["SELECT ... FROM b WHERE ..."; 3]);
+    }
+
+    #[test]
+    fn test_collapse_union_all_consecutive_only() {
+        let fingerprinter = Fingerprinter::new().collapse_union_all(true);
+        let result = fingerprinter.fingerprint_one(
+            "SELECT a FROM b UNION ALL SELECT a FROM b UNION ALL SELECT a FROM c UNION ALL SELECT a FROM b",
+        );
+        assert_eq!(
+            result,
+            "SELECT ... FROM b UNION ALL SELECT ... FROM c UNION ALL SELECT ... FROM b"
+        );
+    }
+
+    #[test]
+    fn test_collapse_union_all_subquery() {
+        let fingerprinter = Fingerprinter::new().collapse_union_all(true);
+        let result = fingerprinter.fingerprint_one(
+            "SELECT * FROM (SELECT a FROM b WHERE c = 1 UNION ALL SELECT a FROM b WHERE c = 2) AS d",
+        );
+        assert_eq!(result, "SELECT * FROM (SELECT ... FROM b WHERE ...) AS d");
+    }
+
+    #[test]
+    fn test_collapse_union_all_other_operations() {
+        let fingerprinter = Fingerprinter::new().collapse_union_all(true);
+        let result = fingerprinter.fingerprint_many(vec![
+            "SELECT a FROM b UNION SELECT a FROM b",
+            "SELECT a FROM b INTERSECT ALL SELECT a FROM b",
+        ]);
+        assert_eq!(
+            result,
+            vec![
+                "SELECT ... FROM b UNION SELECT ... FROM b",
+                "SELECT ... FROM b INTERSECT ALL SELECT ... FROM b",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_collapse_union_all_default_off() {
+        let result = fingerprint_one("SELECT a FROM b UNION ALL SELECT a FROM b", None);
+        assert_eq!(result, "SELECT ... FROM b UNION ALL SELECT ... FROM b");
+    }
+
+    #[test]
+    fn test_sort_commutative_operands_and_or() {
+        let fingerprinter = Fingerprinter::new().sort_commutative_operands(true);
+        let result = fingerprinter.fingerprint_many(vec![
+            "SELECT a FROM b GROUP BY a HAVING SUM(c) > 1 AND (MAX(d) = 2 OR MIN(d) = 3)",
+            "SELECT a FROM b GROUP BY a HAVING (MIN(d) = 3 OR MAX(d) = 2) AND SUM(c) > 1",
+        ]);
+        assert_eq!(
+            result,
+            vec![
+                "SELECT ... FROM b GROUP BY ... HAVING (MAX(d) = 2 OR MIN(d) = 3) AND SUM(c) > 1";
+                2
+            ]
+        );
+    }
+
+    #[test]
+    fn test_sort_commutative_operands_equality() {
+        let fingerprinter = Fingerprinter::new().sort_commutative_operands(true);
+        let result = fingerprinter.fingerprint_many(vec![
+            "MERGE INTO t USING s ON t.id = s.id WHEN MATCHED THEN DELETE",
+            "MERGE INTO t USING s ON s.id = t.id WHEN MATCHED THEN DELETE",
+        ]);
+        assert_eq!(
+            result,
+            vec!["MERGE INTO t USING s ON s.id = t.id WHEN MATCHED THEN DELETE"; 2]
+        );
+    }
+
+    #[test]
+    fn test_sort_commutative_operands_equality_value_right() {
+        let fingerprinter = Fingerprinter::new().sort_commutative_operands(true);
+        let result =
+            fingerprinter.fingerprint_one("SELECT a FROM b GROUP BY a HAVING 1 <> COUNT(*)");
+        assert_eq!(
+            result,
+            "SELECT ... FROM b GROUP BY ... HAVING COUNT(*) <> 1"
+        );
+    }
+
+    #[test]
+    fn test_sort_commutative_operands_placeholders() {
+        let fingerprinter = Fingerprinter::new()
+            .dialect(&PostgreSqlDialect {})
+            .placeholder_style(PlaceholderStyle::DollarNumbered)
+            .sort_commutative_operands(true);
+        let result = fingerprinter.fingerprint_many(vec![
+            "SELECT a FROM b GROUP BY a HAVING SUM(c) = $1 AND COUNT(*) = $2",
+            "SELECT a FROM b GROUP BY a HAVING COUNT(*) = $1 AND SUM(c) = $2",
+        ]);
+        assert_eq!(
+            result,
+            vec!["SELECT ... FROM b GROUP BY ... HAVING COUNT(*) = $1 AND SUM(c) = $2"; 2]
+        );
+    }
+
+    #[test]
+    fn test_sort_commutative_operands_from_tables() {
+        let fingerprinter = Fingerprinter::new().sort_commutative_operands(true);
+        let result =
+            fingerprinter.fingerprint_many(vec!["SELECT * FROM c, b, a", "SELECT * FROM a, c, b"]);
+        assert_eq!(result, vec!["SELECT * FROM a, b, c"; 2]);
+    }
+
+    #[test]
+    fn test_sort_commutative_operands_from_tables_lateral() {
+        let fingerprinter = Fingerprinter::new().sort_commutative_operands(true);
+        let result = fingerprinter.fingerprint_one("SELECT * FROM c, LATERAL (SELECT 1) AS b");
+        assert_eq!(result, "SELECT * FROM c, LATERAL (SELECT ...) AS b");
+    }
+
+    #[test]
+    fn test_sort_commutative_operands_from_tables_anonymize_aliases() {
+        let fingerprinter = Fingerprinter::new()
+            .sort_commutative_operands(true)
+            .anonymize_aliases(true);
+        let result = fingerprinter.fingerprint_many(vec![
+            "SELECT x.* FROM c AS x, b AS y",
+            "SELECT x.* FROM b AS y, c AS x",
+            "WITH w AS (SELECT 1), v AS (SELECT 2) SELECT v.* FROM w, v",
+        ]);
+        assert_eq!(
+            result,
+            vec![
+                "SELECT t2.* FROM b AS t1, c AS t2",
+                "SELECT t2.* FROM b AS t1, c AS t2",
+                "WITH t1 AS (SELECT ...), t2 AS (SELECT ...) SELECT t2.* FROM t1, t2",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_sort_commutative_operands_from_tables_function() {
+        let fingerprinter = Fingerprinter::new().sort_commutative_operands(true);
+        let result = fingerprinter.fingerprint_one("SELECT * FROM t, generate_series(1, t.n) AS g");
+        assert_eq!(result, "SELECT * FROM t, generate_series(1, t.n) AS g");
+    }
+
+    #[test]
+    fn test_sort_commutative_operands_in_list_columns() {
+        let fingerprinter = Fingerprinter::new().sort_commutative_operands(true);
+        let result = fingerprinter.fingerprint_many(vec![
+            "SELECT a FROM b GROUP BY a HAVING (MAX(d), MIN(c)) IN ((1, 2), (3, 4))",
+            "SELECT a FROM b GROUP BY a HAVING (MIN(c), MAX(d)) IN ((2, 1), (4, 3))",
+        ]);
+        assert_eq!(
+            result,
+            vec!["SELECT ... FROM b GROUP BY ... HAVING (MAX(d), MIN(c)) IN ((1, 2), (3, 4))"; 2]
+        );
+    }
+
+    #[test]
+    fn test_sort_commutative_operands_default_off() {
+        let result = fingerprint_one("SELECT * FROM c, b", None);
+        assert_eq!(result, "SELECT * FROM c, b");
+    }
+
+    #[test]
+    fn test_analyze_tables_insert_select() {
+        let analysis = Fingerprinter::new().analyze_one(
+            "INSERT INTO a SELECT * FROM b JOIN c ON b.id = c.id WHERE b.x IN (SELECT x FROM d)",
+        );
+        assert_eq!(
+            analysis.tables,
+            vec![
+                TableReference::new("a", TableRole::Write),
+                TableReference::new("b", TableRole::Read),
+                TableReference::new("c", TableRole::Read),
+                TableReference::new("d", TableRole::Read),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_analyze_tables_insert_select_same_table() {
+        let analysis = Fingerprinter::new().analyze_one("INSERT INTO a SELECT * FROM a");
+        assert_eq!(
+            analysis.tables,
+            vec![
+                TableReference::new("a", TableRole::Write),
+                TableReference::new("a", TableRole::Read),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_analyze_tables_cte_scope() {
+        let analysis = Fingerprinter::new()
+            .analyze_one("WITH users AS (SELECT 1) SELECT * FROM users; SELECT * FROM users");
+        assert_eq!(
+            analysis.tables,
+            vec![TableReference::new("users", TableRole::Read)]
+        );
+        let analysis = Fingerprinter::new().analyze_one(
+            "SELECT * FROM (WITH users AS (SELECT 1) SELECT * FROM users) s JOIN users ON true",
+        );
+        assert_eq!(
+            analysis.tables,
+            vec![TableReference::new("users", TableRole::Read)]
+        );
+    }
+
+    #[test]
+    fn test_analyze_tables_update_from_cte() {
+        let analysis = Fingerprinter::new()
+            .dialect(&PostgreSqlDialect {})
+            .analyze_one(
+                "WITH x AS (SELECT id FROM b) UPDATE a SET c = 1 FROM x WHERE a.id = x.id",
+            );
+        assert_eq!(
+            analysis.tables,
+            vec![
+                TableReference::new("b", TableRole::Read),
+                TableReference::new("a", TableRole::Write),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_analyze_tables_delete() {
+        let analysis = Fingerprinter::new()
+            .dialect(&MySqlDialect {})
+            .analyze_one("DELETE a FROM a JOIN b ON a.id = b.id");
+        assert_eq!(
+            analysis.tables,
+            vec![
+                TableReference::new("a", TableRole::Write),
+                TableReference::new("a", TableRole::Read),
+                TableReference::new("b", TableRole::Read),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_analyze_tables_ddl() {
+        let analysis = Fingerprinter::new().analyze_many(vec![
+            "CREATE TABLE a AS SELECT * FROM b",
+            "ALTER TABLE a ADD COLUMN c INT",
+            "DROP TABLE a, b",
+        ]);
+        let tables: Vec<_> = analysis
+            .into_iter()
+            .map(|analysis| analysis.tables)
+            .collect();
+        assert_eq!(
+            tables,
+            vec![
+                vec![
+                    TableReference::new("a", TableRole::DdlTarget),
+                    TableReference::new("b", TableRole::Read),
+                ],
+                vec![TableReference::new("a", TableRole::DdlTarget)],
+                vec![
+                    TableReference::new("a", TableRole::DdlTarget),
+                    TableReference::new("b", TableRole::DdlTarget),
+                ],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_analyze_tables_lock() {
+        let analysis = Fingerprinter::new()
+            .dialect(&PostgreSqlDialect {})
+            .analyze_many(vec![
+                "LOCK TABLE a IN SHARE MODE",
+                "SELECT * FROM a AS x JOIN b ON x.id = b.id FOR UPDATE OF x",
+            ]);
+        let tables: Vec<_> = analysis
+            .into_iter()
+            .map(|analysis| analysis.tables)
+            .collect();
+        assert_eq!(
+            tables,
+            vec![
+                vec![TableReference::new("a", TableRole::LockTarget)],
+                vec![
+                    TableReference::new("a", TableRole::LockTarget),
+                    TableReference::new("a", TableRole::Read),
+                    TableReference::new("b", TableRole::Read),
+                ],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_analyze_tables_normalized() {
+        let analysis = Fingerprinter::new()
+            .dialect(&PostgreSqlDialect {})
+            .schema_qualification(SchemaQualification::StripDefault("public".to_string()))
+            .analyze_one(r#"SELECT * FROM "public"."users", "Orders", generate_series(1, 2)"#);
+        assert_eq!(
+            analysis.tables,
+            vec![
+                TableReference::new("users", TableRole::Read),
+                TableReference::new(r#""Orders""#, TableRole::Read),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_analyze_columns() {
+        let analysis = Fingerprinter::new().analyze_one(
+            "SELECT u.name, COUNT(*) AS n FROM users u JOIN orders o ON o.user_id = u.id WHERE o.status = 'paid' GROUP BY u.name HAVING COUNT(*) > 1 ORDER BY n, u.name",
+        );
+        assert_eq!(
+            analysis.columns,
+            vec![
+                ColumnReference::new(Some("users"), "name", ColumnClause::Projection),
+                ColumnReference::new(Some("orders"), "user_id", ColumnClause::Join),
+                ColumnReference::new(Some("users"), "id", ColumnClause::Join),
+                ColumnReference::new(Some("orders"), "status", ColumnClause::Where),
+                ColumnReference::new(Some("users"), "name", ColumnClause::GroupBy),
+                ColumnReference::new(Some("users"), "name", ColumnClause::OrderBy),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_analyze_columns_cte_scope() {
+        let analysis = Fingerprinter::new().analyze_one(
+            "WITH users AS (SELECT 1 AS id) SELECT id FROM users; SELECT id FROM users",
+        );
+        assert_eq!(
+            analysis.columns,
+            vec![
+                ColumnReference::new(None, "id", ColumnClause::Projection),
+                ColumnReference::new(Some("users"), "id", ColumnClause::Projection),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_analyze_columns_unqualified() {
+        let analysis = Fingerprinter::new().analyze_many(vec![
+            "SELECT a FROM b WHERE c = 1",
+            "SELECT a FROM b JOIN d ON b.id = d.id WHERE c = 1",
+        ]);
+        let columns: Vec<_> = analysis
+            .into_iter()
+            .map(|analysis| analysis.columns)
+            .collect();
+        assert_eq!(
+            columns,
+            vec![
+                vec![
+                    ColumnReference::new(Some("b"), "a", ColumnClause::Projection),
+                    ColumnReference::new(Some("b"), "c", ColumnClause::Where),
+                ],
+                vec![
+                    ColumnReference::new(None, "a", ColumnClause::Projection),
+                    ColumnReference::new(Some("b"), "id", ColumnClause::Join),
+                    ColumnReference::new(Some("d"), "id", ColumnClause::Join),
+                    ColumnReference::new(None, "c", ColumnClause::Where),
+                ],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_analyze_columns_correlated_subquery() {
+        let analysis = Fingerprinter::new().analyze_one(
+            "SELECT id FROM users WHERE EXISTS (SELECT 1 FROM orders o WHERE o.user_id = users.id)",
+        );
+        assert_eq!(
+            analysis.columns,
+            vec![
+                ColumnReference::new(Some("users"), "id", ColumnClause::Projection),
+                ColumnReference::new(Some("orders"), "user_id", ColumnClause::Where),
+                ColumnReference::new(Some("users"), "id", ColumnClause::Where),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_analyze_columns_derived_and_cte() {
+        let analysis = Fingerprinter::new().analyze_many(vec![
+            "SELECT x.a FROM (SELECT a FROM b) AS x",
+            "WITH x AS (SELECT a FROM b) SELECT * FROM x",
+        ]);
+        let columns: Vec<_> = analysis
+            .into_iter()
+            .map(|analysis| analysis.columns)
+            .collect();
+        assert_eq!(
+            columns,
+            vec![
+                vec![
+                    ColumnReference::new(Some("b"), "a", ColumnClause::Projection),
+                    ColumnReference::new(None, "a", ColumnClause::Projection),
+                ],
+                vec![
+                    ColumnReference::new(Some("b"), "a", ColumnClause::Projection),
+                    ColumnReference::new(None, "*", ColumnClause::Projection),
+                ],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_analyze_columns_update_delete() {
+        let analysis = Fingerprinter::new()
+            .dialect(&PostgreSqlDialect {})
+            .analyze_many(vec![
+                "UPDATE a SET b = 1 WHERE c = 2",
+                "DELETE FROM a USING b WHERE a.id = b.id",
+            ]);
+        let columns: Vec<_> = analysis
+            .into_iter()
+            .map(|analysis| analysis.columns)
+            .collect();
+        assert_eq!(
+            columns,
+            vec![
+                vec![ColumnReference::new(Some("a"), "c", ColumnClause::Where)],
+                vec![
+                    ColumnReference::new(Some("a"), "id", ColumnClause::Where),
+                    ColumnReference::new(Some("b"), "id", ColumnClause::Where),
+                ],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_analyze_columns_normalized() {
+        let analysis = Fingerprinter::new()
+            .dialect(&PostgreSqlDialect {})
+            .analyze_one(r#"SELECT "name", "Name" FROM "users""#);
+        assert_eq!(
+            analysis.columns,
+            vec![
+                ColumnReference::new(Some("users"), "name", ColumnClause::Projection),
+                ColumnReference::new(Some("users"), r#""Name""#, ColumnClause::Projection),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_analyze_metrics() {
+        let analysis = Fingerprinter::new().analyze_one(
+            "SELECT a, b, c FROM t JOIN u ON t.id = u.id, v WHERE a IN (1, 2, 3) AND b = 'x' AND c IS NULL",
+        );
+        assert_eq!(
+            analysis.metrics,
+            QueryMetrics {
+                joins: 2,
+                subqueries: 0,
+                max_nesting_depth: 0,
+                ctes: 0,
+                set_operations: 0,
+                predicates: 4,
+                projection_width: 3,
+                elided_values: 4,
+            }
+        );
+    }
+
+    #[test]
+    fn test_analyze_metrics_nesting() {
+        let analysis = Fingerprinter::new().analyze_one(
+            "WITH x AS (SELECT 1), y AS (SELECT * FROM (SELECT 2) AS z) SELECT * FROM x WHERE EXISTS (SELECT 1 FROM y WHERE y.a IN (SELECT 3))",
+        );
+        let metrics = analysis.metrics;
+        assert_eq!(metrics.subqueries, 3);
+        assert_eq!(metrics.max_nesting_depth, 2);
+        assert_eq!(metrics.ctes, 2);
+    }
+
+    #[test]
+    fn test_analyze_metrics_set_operations() {
+        let analysis =
+            Fingerprinter::new().analyze_one("(SELECT 1) UNION (SELECT 2) UNION ALL SELECT 3");
+        let metrics = analysis.metrics;
+        assert_eq!(metrics.set_operations, 2);
+        assert_eq!(metrics.subqueries, 0);
+        assert_eq!(metrics.projection_width, 1);
+        assert_eq!(metrics.elided_values, 3);
+    }
+
+    #[test]
+    fn test_analyze_metrics_set_operations_with_subqueries() {
+        let analysis = Fingerprinter::new().analyze_one(
+            "WITH x AS (SELECT 1) SELECT (SELECT 1) UNION (SELECT * FROM (SELECT 2) AS y) ORDER BY (SELECT 3)",
+        );
+        let metrics = analysis.metrics;
+        assert_eq!(metrics.set_operations, 1);
+        assert_eq!(metrics.ctes, 1);
+        assert_eq!(metrics.subqueries, 3);
+        assert_eq!(metrics.max_nesting_depth, 1);
+    }
+
+    #[test]
+    fn test_analyze_metrics_update_subquery() {
+        let analysis =
+            Fingerprinter::new().analyze_one("UPDATE t SET a = 1 WHERE b IN (SELECT c FROM d)");
+        let metrics = analysis.metrics;
+        assert_eq!(metrics.subqueries, 1);
+        assert_eq!(metrics.max_nesting_depth, 1);
+        assert_eq!(metrics.predicates, 1);
+        assert_eq!(metrics.elided_values, 1);
+    }
+
+    #[test]
+    fn test_analyze_metrics_elided_values_batch_size() {
+        let analysis = Fingerprinter::new().analyze_many(vec![
+            "SELECT * FROM t WHERE id IN (1, 2)",
+            "SELECT * FROM t WHERE id IN (1, 2, 3, 4)",
+        ]);
+        assert_eq!(analysis[0].fingerprint, analysis[1].fingerprint);
+        assert_eq!(analysis[0].metrics.elided_values, 2);
+        assert_eq!(analysis[1].metrics.elided_values, 4);
+    }
+
+    #[test]
+    fn test_analyze_transactions_tree() {
+        let report = Fingerprinter::new().analyze_transactions(vec![
+            "BEGIN",
+            "SAVEPOINT a",
+            "SAVEPOINT b",
+            "ROLLBACK TO SAVEPOINT b",
+            "RELEASE SAVEPOINT a",
+            "COMMIT",
+        ]);
+        assert_eq!(
+            report,
+            TransactionReport {
+                transactions: vec![Transaction {
+                    start: 0,
+                    end: Some(5),
+                    rolled_back: false,
+                    savepoints: vec![Savepoint {
+                        name: "s1".to_string(),
+                        start: 1,
+                        released: Some(4),
+                        rollbacks: vec![],
+                        savepoints: vec![Savepoint {
+                            name: "s2".to_string(),
+                            start: 2,
+                            released: Some(4),
+                            rollbacks: vec![3],
+                            savepoints: vec![],
+                        }],
+                    }],
+                }],
+                max_depth: 3,
+                unreleased_savepoints: vec![],
+                unknown_savepoints: vec![],
+                outside_transaction: vec![],
+                unexpected_transaction_control: vec![],
+            }
+        );
+    }
+
+    #[test]
+    fn test_analyze_transactions_unreleased_savepoints() {
+        let report = Fingerprinter::new().analyze_transactions(vec![
+            "BEGIN",
+            "SAVEPOINT a",
+            "ROLLBACK",
+            "START TRANSACTION",
+            "SAVEPOINT b",
+        ]);
+        assert_eq!(report.unreleased_savepoints, vec![1, 4]);
+        assert!(report.transactions[0].rolled_back);
+        assert_eq!(report.transactions[1].end, None);
+    }
+
+    #[test]
+    fn test_analyze_transactions_unknown_savepoints() {
+        let report = Fingerprinter::new().analyze_transactions(vec![
+            "BEGIN",
+            "SAVEPOINT a",
+            "RELEASE SAVEPOINT a",
+            "ROLLBACK TO SAVEPOINT a",
+            "RELEASE SAVEPOINT b",
+            "COMMIT",
+        ]);
+        assert_eq!(report.unknown_savepoints, vec![3, 4]);
+    }
+
+    #[test]
+    fn test_analyze_transactions_rollback_discards_later_savepoints() {
+        let report = Fingerprinter::new().analyze_transactions(vec![
+            "BEGIN",
+            "SAVEPOINT a",
+            "SAVEPOINT b",
+            "ROLLBACK TO SAVEPOINT a",
+            "RELEASE SAVEPOINT b",
+            "RELEASE SAVEPOINT a",
+            "COMMIT",
+        ]);
+        assert_eq!(report.unknown_savepoints, vec![4]);
+        let savepoint = &report.transactions[0].savepoints[0];
+        assert_eq!(savepoint.released, Some(5));
+        assert_eq!(savepoint.savepoints[0].released, None);
+        assert_eq!(report.unreleased_savepoints, vec![]);
+    }
+
+    #[test]
+    fn test_analyze_transactions_outside_transaction() {
+        let report = Fingerprinter::new().analyze_transactions(vec![
+            "SELECT 1",
+            "BEGIN",
+            "SELECT 2",
+            "COMMIT",
+            "UPDATE t SET a = 1",
+            "SELECT SELECT SELECT",
+            "COMMIT",
+        ]);
+        assert_eq!(report.outside_transaction, vec![0, 4, 5]);
+        assert_eq!(report.max_depth, 1);
+    }
+
+    #[test]
+    fn test_analyze_transactions_unexpected_transaction_control() {
+        let report = Fingerprinter::new().analyze_transactions(vec![
+            "SAVEPOINT s1",
+            "COMMIT",
+            "BEGIN",
+            "BEGIN",
+            "SELECT 1",
+            "COMMIT",
+            "ROLLBACK",
+        ]);
+        assert_eq!(report.unexpected_transaction_control, vec![0, 1, 3, 6]);
+        assert_eq!(report.transactions.len(), 1);
+        assert_eq!(report.outside_transaction, Vec::<usize>::new());
+    }
+
+    #[test]
+    fn test_repetition_detector() {
+        let fingerprints = ["A", "B", "B", "B", "C", "B", "B"];
+        let repeats = RepetitionDetector::new(2).detect(&fingerprints);
+        assert_eq!(
+            repeats,
+            vec![
+                RepeatedQueries {
+                    fingerprints: vec!["B".to_string()],
+                    count: 3,
+                    range: 1..4,
+                },
+                RepeatedQueries {
+                    fingerprints: vec!["B".to_string()],
+                    count: 2,
+                    range: 5..7,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_repetition_detector_min_repetitions() {
+        let fingerprints = ["A", "B", "B", "C", "C", "C"];
+        let repeats = RepetitionDetector::new(3).detect(&fingerprints);
+        assert_eq!(
+            repeats,
+            vec![RepeatedQueries {
+                fingerprints: vec!["C".to_string()],
+                count: 3,
+                range: 3..6,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_repetition_detector_pattern() {
+        let fingerprints = ["A", "B", "C", "B", "C", "B", "C", "D"];
+        let repeats = RepetitionDetector::new(3)
+            .max_pattern_length(3)
+            .detect(&fingerprints);
+        assert_eq!(
+            repeats,
+            vec![RepeatedQueries {
+                fingerprints: vec!["B".to_string(), "C".to_string()],
+                count: 3,
+                range: 1..7,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_repetition_detector_pattern_prefers_shortest() {
+        let fingerprints = ["A", "A", "A", "A"];
+        let repeats = RepetitionDetector::new(2)
+            .max_pattern_length(2)
+            .detect(&fingerprints);
+        assert_eq!(
+            repeats,
+            vec![RepeatedQueries {
+                fingerprints: vec!["A".to_string()],
+                count: 4,
+                range: 0..4,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_repetition_detector_pattern_not_default() {
+        let fingerprints = ["A", "B", "A", "B"];
+        let repeats = RepetitionDetector::new(2).detect(&fingerprints);
+        assert_eq!(repeats, vec![]);
+    }
+
+    #[test]
+    fn test_repetition_detector_fingerprints() {
+        let fingerprints = fingerprint_many(
+            vec![
+                "SELECT * FROM books WHERE author_id = 1",
+                "SELECT * FROM books WHERE author_id = 2",
+            ],
+            None,
+        );
+        let repeats = RepetitionDetector::new(2).detect(&fingerprints);
+        assert_eq!(
+            repeats[0].fingerprints,
+            vec!["SELECT * FROM books WHERE ..."]
+        );
+    }
+
+    #[test]
+    fn test_group_many_count() {
+        let groups = Fingerprinter::new().group_many(
+            vec![
+                "SELECT * FROM a",
+                "SELECT * FROM b WHERE id = 1",
+                "SELECT * FROM c",
+                "SELECT * FROM b WHERE id = 2",
+                "SELECT * FROM c",
+                "SELECT * FROM b WHERE id = 3",
+            ],
+            GroupOrder::Count,
+        );
+        let summary: Vec<_> = groups
+            .iter()
+            .map(|group| {
+                (
+                    group.fingerprint.as_str(),
+                    group.count,
+                    group.first,
+                    group.last,
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("SELECT * FROM b WHERE ...", 3, 1, 5),
+                ("SELECT * FROM c", 2, 2, 4),
+                ("SELECT * FROM a", 1, 0, 0),
+            ]
+        );
+        assert_eq!(groups[0].example, "SELECT * FROM b WHERE id = 1");
+        assert_eq!(groups[0].durations, vec![]);
+    }
+
+    #[test]
+    fn test_group_many_first_appearance() {
+        let groups = Fingerprinter::new().group_many(
+            vec!["SELECT * FROM a", "SELECT * FROM b", "SELECT * FROM b"],
+            GroupOrder::FirstAppearance,
+        );
+        let fingerprints: Vec<_> = groups
+            .iter()
+            .map(|group| group.fingerprint.as_str())
+            .collect();
+        assert_eq!(fingerprints, vec!["SELECT * FROM a", "SELECT * FROM b"]);
+    }
+
+    #[test]
+    fn test_group_many_count_ties() {
+        let groups = Fingerprinter::new().group_many(
+            vec![
+                "SELECT * FROM a",
+                "SELECT * FROM b",
+                "SELECT * FROM b",
+                "SELECT * FROM a",
+            ],
+            GroupOrder::Count,
+        );
+        let fingerprints: Vec<_> = groups
+            .iter()
+            .map(|group| group.fingerprint.as_str())
+            .collect();
+        assert_eq!(fingerprints, vec!["SELECT * FROM a", "SELECT * FROM b"]);
+    }
+
+    #[test]
+    fn test_group_many_with_durations() {
+        let groups = Fingerprinter::new().group_many_with_durations(
+            vec![
+                ("SELECT * FROM a WHERE id = 1", Duration::from_millis(3)),
+                ("SELECT * FROM b", Duration::from_millis(1)),
+                ("SELECT * FROM a WHERE id = 2", Duration::from_millis(5)),
+            ],
+            GroupOrder::Count,
+        );
+        assert_eq!(
+            groups[0].durations,
+            vec![Duration::from_millis(3), Duration::from_millis(5)]
+        );
+        assert_eq!(groups[0].total_duration(), Duration::from_millis(8));
+        assert_eq!(groups[1].total_duration(), Duration::from_millis(1));
+    }
+
+    #[test]
+    fn test_fingerprint_with_values_select() {
+        let result = Fingerprinter::new().fingerprint_one_with_values(
+            "SELECT a, b AS c FROM t JOIN u ON t.id = u.id WHERE x = 1 ORDER BY a DESC, b LIMIT 5",
+        );
+        assert_eq!(
+            result.fingerprint,
+            "SELECT ... FROM t JOIN u ON ... WHERE ... ORDER BY ... DESC LIMIT ..."
+        );
+        assert_eq!(
+            result.values,
+            vec!["a, b AS c", "t.id = u.id", "x = 1", "a, b", "5"]
+        );
+    }
+
+    #[test]
+    fn test_fingerprint_with_values_insert() {
+        let result = Fingerprinter::new()
+            .dialect(&PostgreSqlDialect {})
+            .fingerprint_one_with_values(
+                "INSERT INTO t (a, b) VALUES (1, 'x'), (2, 'y') ON CONFLICT (a) DO UPDATE SET b = excluded.b RETURNING id",
+            );
+        assert_eq!(
+            result.fingerprint,
+            "INSERT INTO t (...) VALUES (...) ON CONFLICT(...) DO UPDATE SET ... = ... RETURNING ..."
+        );
+        assert_eq!(
+            result.values,
+            vec!["a, b", "1, 'x'), (2, 'y'", "a", "b", "excluded.b", "id"]
+        );
+    }
+
+    #[test]
+    fn test_fingerprint_with_values_group_by() {
+        let result = Fingerprinter::new()
+            .fingerprint_one_with_values("SELECT 1 FROM t GROUP BY a, b, ROLLUP (c, (d, e)), f");
+        assert_eq!(
+            result.fingerprint,
+            "SELECT ... FROM t GROUP BY ..., ROLLUP (...), ..."
+        );
+        assert_eq!(result.values, vec!["1", "a, b", "c, (d, e)", "f"]);
+    }
+
+    #[test]
+    fn test_fingerprint_with_values_multi_row_insert() {
+        let sql = "INSERT INTO t VALUES (1, 2), (3, 4)";
+        let result = Fingerprinter::new().fingerprint_one_with_values(sql);
+        assert_eq!(result.fingerprint, "INSERT INTO t VALUES (...)");
+        assert_eq!(result.values, vec!["1, 2), (3, 4"]);
+        assert_eq!(result.fingerprint.replace("...", &result.values[0]), sql);
+    }
+
+    #[test]
+    fn test_fingerprint_with_values_collapsed() {
+        let result = Fingerprinter::new()
+            .collapse_union_all(true)
+            .fingerprint_one_with_values(
+                "SELECT a FROM t WHERE b = 1 UNION ALL SELECT a FROM t WHERE b = 2",
+            );
+        assert_eq!(result.fingerprint, "SELECT ... FROM t WHERE ...");
+        assert_eq!(result.values, vec!["a", "b = 1"]);
+    }
+
+    #[test]
+    fn test_fingerprint_with_values_marker_characters() {
+        let result = Fingerprinter::new().fingerprint_one_with_values(
+            "SELECT \"a\0b\" FROM \"t\0\u{E000}1\u{E000}\" WHERE x = '\0'",
+        );
+        assert_eq!(
+            result.fingerprint,
+            "SELECT ... FROM \"t\0\u{E000}1\u{E000}\" WHERE ..."
+        );
+        assert_eq!(result.values, vec!["\"a\0b\"", "x = '\0'"]);
+    }
+
+    #[test]
+    fn test_fingerprint_with_values_many() {
+        let results = Fingerprinter::new().fingerprint_many_with_values(vec![
+            "SAVEPOINT x",
+            "SELECT * FROM t",
+            "DELETE FROM t WHERE id = 1",
+            "SELECT (",
+        ]);
+        let results: Vec<_> = results
+            .iter()
+            .map(|result| (result.fingerprint.as_str(), result.values.clone()))
+            .collect();
+        assert_eq!(
+            results,
+            vec![
+                ("SAVEPOINT s1", vec![]),
+                ("SELECT * FROM t", vec![]),
+                ("DELETE FROM t WHERE ...", vec!["id = 1".to_string()]),
+                ("SELECT (", vec![]),
+            ]
+        );
+    }
+
+    fn diff_changes(diff: &TraceDiff) -> Vec<(TraceChange, &str)> {
+        diff.lines
+            .iter()
+            .map(|line| (line.change, line.fingerprint.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn test_diff_traces_identical() {
+        let diff = diff_traces(&["SELECT a", "SELECT b"], &["SELECT a", "SELECT b"]);
+        assert!(diff.is_empty());
+        assert_eq!(diff.counts, vec![]);
+        assert_eq!(diff.to_string(), "  SELECT a\n  SELECT b\n");
+    }
+
+    #[test]
+    fn test_diff_traces_added_and_removed() {
+        let diff = diff_traces(
+            &["SELECT a", "SELECT b", "SELECT d"],
+            &["SELECT a", "SELECT c", "SELECT d"],
+        );
+        assert!(!diff.is_empty());
+        assert_eq!(
+            diff_changes(&diff),
+            vec![
+                (TraceChange::Unchanged, "SELECT a"),
+                (TraceChange::Removed, "SELECT b"),
+                (TraceChange::Added, "SELECT c"),
+                (TraceChange::Unchanged, "SELECT d"),
+            ]
+        );
+        assert_eq!(
+            diff.to_string(),
+            "  SELECT a\n- SELECT b\n+ SELECT c\n  SELECT d\n\nCount changes:\n  1 -> 0  SELECT b\n  0 -> 1  SELECT c\n"
+        );
+    }
+
+    #[test]
+    fn test_diff_traces_moved() {
+        let diff = diff_traces(
+            &["SELECT a", "SELECT b", "SELECT c"],
+            &["SELECT b", "SELECT c", "SELECT a"],
+        );
+        assert_eq!(
+            diff_changes(&diff),
+            vec![
+                (TraceChange::MovedFrom, "SELECT a"),
+                (TraceChange::Unchanged, "SELECT b"),
+                (TraceChange::Unchanged, "SELECT c"),
+                (TraceChange::MovedTo, "SELECT a"),
+            ]
+        );
+        assert_eq!(
+            (diff.lines[0].before, diff.lines[0].after),
+            (Some(0), Some(2))
+        );
+        assert_eq!(
+            (diff.lines[3].before, diff.lines[3].after),
+            (Some(0), Some(2))
+        );
+        assert_eq!(diff.counts, vec![]);
+    }
+
+    #[test]
+    fn test_diff_traces_moved_counts() {
+        let diff = diff_traces(&["b", "a"], &["a", "b", "b"]);
+        assert_eq!(
+            diff.counts,
+            vec![FingerprintCountChange {
+                fingerprint: "b".to_string(),
+                before: 1,
+                after: 2,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_diff_traces_positions() {
+        let diff = diff_traces(
+            &["SELECT a", "SELECT b", "SELECT z"],
+            &["SELECT a", "SELECT b", "SELECT b", "SELECT z"],
+        );
+        let positions: Vec<_> = diff
+            .lines
+            .iter()
+            .map(|line| (line.change, line.before, line.after))
+            .collect();
+        assert_eq!(
+            positions,
+            vec![
+                (TraceChange::Unchanged, Some(0), Some(0)),
+                (TraceChange::Unchanged, Some(1), Some(1)),
+                (TraceChange::Added, None, Some(2)),
+                (TraceChange::Unchanged, Some(2), Some(3)),
+            ]
+        );
+        assert_eq!(
+            diff.counts,
+            vec![FingerprintCountChange {
+                fingerprint: "SELECT b".to_string(),
+                before: 1,
+                after: 2,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_diff_traces_empty() {
+        let diff = diff_traces::<&str>(&[], &["SELECT a"]);
+        assert_eq!(diff_changes(&diff), vec![(TraceChange::Added, "SELECT a")]);
+        let diff = diff_traces::<&str>(&["SELECT a"], &[]);
+        assert_eq!(
+            diff_changes(&diff),
+            vec![(TraceChange::Removed, "SELECT a")]
+        );
+    }
+
+    #[test]
+    fn test_diff_traces_large() {
+        let before: Vec<String> = (0..4_000).map(|i| format!("SELECT {}", i % 7)).collect();
+        let mut after = before.clone();
+        after.remove(2_000);
+        after.insert(0, "DELETE FROM t".to_string());
+        after.push("UPDATE t SET a = 1".to_string());
+        let diff = diff_traces(&before, &after);
+        assert_eq!(diff.lines.len(), 4_002);
+        assert_eq!(
+            diff.counts
+                .iter()
+                .map(|count| (count.fingerprint.as_str(), count.before, count.after))
+                .collect::<Vec<_>>(),
+            vec![
+                ("DELETE FROM t", 0, 1),
+                ("SELECT 5", 571, 570),
+                ("UPDATE t SET a = 1", 0, 1),
+            ]
+        );
+    }
+
+    fn lineage_edge(
+        source: LineageNode,
+        target: LineageNode,
+        columns: &[(&str, &str)],
+    ) -> LineageEdge {
+        LineageEdge {
+            source,
+            target,
+            columns: columns
+                .iter()
+                .map(|(source, target)| ColumnLineage::new(*source, *target))
+                .collect(),
+        }
+    }
+
+    fn table_node(name: &str) -> LineageNode {
+        LineageNode::Table(name.to_string())
+    }
+
+    #[test]
+    fn test_lineage_insert_select() {
+        let analysis = Fingerprinter::new().analyze_one(
+            "INSERT INTO target (x, y, z) SELECT a.p, q, b.r + 1 FROM a JOIN b ON a.id = b.id",
+        );
+        assert_eq!(
+            analysis.lineage,
+            vec![
+                lineage_edge(table_node("a"), table_node("target"), &[("p", "x")]),
+                lineage_edge(table_node("b"), table_node("target"), &[]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_lineage_insert_select_unqualified() {
+        let analysis =
+            Fingerprinter::new().analyze_one("INSERT INTO t (x, y) SELECT p, q AS r FROM a");
+        assert_eq!(
+            analysis.lineage,
+            vec![lineage_edge(
+                table_node("a"),
+                table_node("t"),
+                &[("p", "x"), ("q", "y")]
+            )]
+        );
+    }
+
+    #[test]
+    fn test_lineage_insert_select_wildcard() {
+        let analysis = Fingerprinter::new().analyze_one("INSERT INTO t (x) SELECT * FROM a");
+        assert_eq!(
+            analysis.lineage,
+            vec![lineage_edge(table_node("a"), table_node("t"), &[])]
+        );
+    }
+
+    #[test]
+    fn test_lineage_insert_values() {
+        let analysis = Fingerprinter::new().analyze_one("INSERT INTO t (x) VALUES (1)");
+        assert_eq!(analysis.lineage, vec![]);
+    }
+
+    #[test]
+    fn test_lineage_insert_cte() {
+        let analysis = Fingerprinter::new()
+            .dialect(&PostgreSqlDialect {})
+            .analyze_one("WITH c AS (SELECT y FROM a) INSERT INTO t (x) SELECT y FROM c");
+        assert_eq!(
+            analysis.lineage,
+            vec![lineage_edge(table_node("a"), table_node("t"), &[])]
+        );
+    }
+
+    #[test]
+    fn test_lineage_cte_scope() {
+        let analysis = Fingerprinter::new().analyze_one(
+            "WITH users AS (SELECT 1) SELECT * FROM users; INSERT INTO x SELECT id FROM users",
+        );
+        assert_eq!(
+            analysis.lineage,
+            vec![lineage_edge(table_node("users"), table_node("x"), &[])]
+        );
+    }
+
+    #[test]
+    fn test_lineage_create_table_as() {
+        let analysis = Fingerprinter::new()
+            .analyze_one("CREATE TABLE x AS SELECT s.a, s.b AS c, 1 AS d FROM public.src AS s");
+        assert_eq!(
+            analysis.lineage,
+            vec![lineage_edge(
+                table_node("public.src"),
+                table_node("x"),
+                &[("a", "a"), ("b", "c")]
+            )]
+        );
+    }
+
+    #[test]
+    fn test_lineage_create_table_as_columns() {
+        let analysis = Fingerprinter::new()
+            .analyze_one("CREATE TABLE x (m INT, n INT) AS SELECT a, b FROM src");
+        assert_eq!(
+            analysis.lineage,
+            vec![lineage_edge(
+                table_node("src"),
+                table_node("x"),
+                &[("a", "m"), ("b", "n")]
+            )]
+        );
+    }
+
+    #[test]
+    fn test_lineage_merge() {
+        let analysis = Fingerprinter::new().analyze_one(
+            "MERGE INTO t USING s ON t.id = s.id WHEN MATCHED THEN UPDATE SET t.v = s.v WHEN NOT MATCHED THEN INSERT (id, v) VALUES (s.id, s.v)",
+        );
+        assert_eq!(
+            analysis.lineage,
+            vec![lineage_edge(
+                table_node("s"),
+                table_node("t"),
+                &[("id", "id"), ("v", "v")]
+            )]
+        );
+    }
+
+    #[test]
+    fn test_lineage_merge_subquery() {
+        let analysis = Fingerprinter::new().analyze_one(
+            "MERGE INTO t USING (SELECT * FROM a) AS s ON t.id = s.id WHEN MATCHED THEN DELETE",
+        );
+        assert_eq!(
+            analysis.lineage,
+            vec![lineage_edge(table_node("a"), table_node("t"), &[])]
+        );
+    }
+
+    #[test]
+    fn test_lineage_copy() {
+        let analysis = Fingerprinter::new()
+            .dialect(&PostgreSqlDialect {})
+            .analyze_many(vec![
+                "COPY t (a, b) FROM STDIN",
+                "COPY t TO '/tmp/t.csv'",
+                "COPY (SELECT * FROM a) TO STDOUT",
+                "COPY t FROM PROGRAM 'gunzip'",
+            ]);
+        let lineage: Vec<_> = analysis
+            .into_iter()
+            .map(|analysis| analysis.lineage)
+            .collect();
+        assert_eq!(
+            lineage,
+            vec![
+                vec![lineage_edge(LineageNode::Stdin, table_node("t"), &[])],
+                vec![lineage_edge(
+                    table_node("t"),
+                    LineageNode::File("/tmp/t.csv".to_string()),
+                    &[]
+                )],
+                vec![lineage_edge(table_node("a"), LineageNode::Stdout, &[])],
+                vec![lineage_edge(
+                    LineageNode::Program("gunzip".to_string()),
+                    table_node("t"),
+                    &[]
+                )],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_lineage_select() {
+        let analysis = Fingerprinter::new().analyze_one("SELECT * FROM a");
+        assert_eq!(analysis.lineage, vec![]);
+    }
 
-fn maybe_unquote_ident(ident: &mut Ident) {
-    let Ident {
-        value, quote_style, ..
-    } = ident;
+    fn kinds(analysis: Vec<Analysis>) -> Vec<(StatementKind, bool)> {
+        analysis
+            .into_iter()
+            .map(|analysis| (analysis.kind, analysis.read_only))
+            .collect()
+    }
 
-    if value.chars().all(|c| c.is_alphanumeric() || c == '_') {
-        *quote_style = None;
+    #[test]
+    fn test_analyze_kind() {
+        let analysis = Fingerprinter::new()
+            .dialect(&PostgreSqlDialect {})
+            .analyze_many(vec![
+                "SELECT a FROM b",
+                "INSERT INTO a VALUES (1)",
+                "INSERT INTO a VALUES (1) ON CONFLICT (id) DO UPDATE SET b = 2",
+                "UPDATE a SET b = 1",
+                "DELETE FROM a",
+                "MERGE INTO a USING b ON a.id = b.id WHEN MATCHED THEN DELETE",
+                "CREATE TABLE a (b INT)",
+                "BEGIN",
+                "SAVEPOINT s1",
+                "ROLLBACK TO SAVEPOINT s1",
+                "COMMIT",
+                "SET search_path = a",
+                "SHOW search_path",
+            ]);
+        assert_eq!(
+            kinds(analysis),
+            vec![
+                (StatementKind::Select, true),
+                (StatementKind::Insert, false),
+                (StatementKind::Upsert, false),
+                (StatementKind::Update, false),
+                (StatementKind::Delete, false),
+                (StatementKind::Merge, false),
+                (StatementKind::Ddl, false),
+                (StatementKind::TransactionControl, true),
+                (StatementKind::Savepoint, true),
+                (StatementKind::Savepoint, true),
+                (StatementKind::TransactionControl, true),
+                (StatementKind::Session, true),
+                (StatementKind::Utility, true),
+            ]
+        );
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    #[test]
+    fn test_analyze_kind_upsert_mysql() {
+        let analysis = Fingerprinter::new()
+            .dialect(&MySqlDialect {})
+            .analyze_many(vec![
+                "INSERT INTO a (b) VALUES (1) ON DUPLICATE KEY UPDATE b = 1",
+                "REPLACE INTO a (b) VALUES (1)",
+                "INSERT IGNORE INTO a (b) VALUES (1)",
+            ]);
+        assert_eq!(
+            kinds(analysis),
+            vec![
+                (StatementKind::Upsert, false),
+                (StatementKind::Upsert, false),
+                (StatementKind::Insert, false),
+            ]
+        );
+    }
 
     #[test]
-    fn test_fingerprint_one() {
-        let result = fingerprint_one("SELECT 123", None);
-        assert_eq!(result, "SELECT ...");
+    fn test_analyze_read_only_select_writes() {
+        let analysis = Fingerprinter::new()
+            .dialect(&PostgreSqlDialect {})
+            .analyze_many(vec![
+                "SELECT * FROM a FOR UPDATE",
+                "SELECT * INTO b FROM a",
+                "WITH d AS (DELETE FROM a RETURNING *) SELECT * FROM d",
+                "WITH x AS (SELECT 1) INSERT INTO a SELECT * FROM x",
+            ]);
+        assert_eq!(
+            kinds(analysis),
+            vec![
+                (StatementKind::Select, false),
+                (StatementKind::Select, false),
+                (StatementKind::Select, false),
+                (StatementKind::Insert, false),
+            ]
+        );
     }
 
     #[test]
-    fn test_empty() {
-        let result = fingerprint_many(vec![""], None);
-        assert_eq!(result, vec![""]);
+    fn test_analyze_read_only_explain() {
+        let analysis = Fingerprinter::new()
+            .dialect(&PostgreSqlDialect {})
+            .analyze_many(vec![
+                "EXPLAIN DELETE FROM a",
+                "EXPLAIN ANALYZE SELECT * FROM a",
+                "EXPLAIN ANALYZE DELETE FROM a",
+            ]);
+        assert_eq!(
+            kinds(analysis),
+            vec![
+                (StatementKind::Utility, true),
+                (StatementKind::Utility, true),
+                (StatementKind::Utility, false),
+            ]
+        );
     }
 
     #[test]
-    fn test_unparsable() {
-        let result = fingerprint_many(vec!["SELECT  SELECT  SELECT  SELECT"], None);
-        assert_eq!(result, vec!["SELECT  SELECT  SELECT  SELECT"]);
+    fn test_analyze_read_only_multiple_statements() {
+        let analysis = Fingerprinter::new().analyze_one("SELECT 1; DELETE FROM a");
+        assert_eq!(analysis.kind, StatementKind::Select);
+        assert!(!analysis.read_only);
     }
 
     #[test]
-    fn test_comments_dropped() {
-        let result = fingerprint_many(vec!["SELECT 123 /* magic value */"], None);
-        assert_eq!(result, vec!["SELECT ..."]);
+    fn test_analyze_kind_unparsable() {
+        let analysis = Fingerprinter::new().analyze_many(vec![
+            "SELECT a FROM b WHERE c = = 1",
+            "SELECT a FROM b WHERE c = = 1 FOR UPDATE",
+            "INSERT INTO a VALUES (1) ON DUPLICATE KEY UPDATE b = = 1",
+            "WITH x AS (SELECT SELECT) UPDATE a SET b = = 1",
+            "DROP TABLE a a a",
+            "FROBNICATE a",
+        ]);
+        assert_eq!(
+            kinds(analysis),
+            vec![
+                (StatementKind::Select, true),
+                (StatementKind::Select, false),
+                (StatementKind::Upsert, false),
+                (StatementKind::Update, false),
+                (StatementKind::Ddl, false),
+                (StatementKind::Unknown, false),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_analyze_unparsable() {
+        let analysis = Fingerprinter::new().analyze_one("SELECT SELECT SELECT SELECT");
+        assert_eq!(analysis.fingerprint, "SELECT SELECT SELECT SELECT");
+        assert_eq!(analysis.tables, vec![]);
     }
 
     #[test]
@@ -511,6 +6901,63 @@ mod tests {
         assert_eq!(result, vec!["SELECT ... FROM c GROUP BY ..."]);
     }
 
+    #[test]
+    fn test_select_with_group_by_rollup() {
+        let result = fingerprint_many(vec!["SELECT a, b FROM c GROUP BY ROLLUP (a, b)"], None);
+        assert_eq!(result, vec!["SELECT ... FROM c GROUP BY ROLLUP (...)"]);
+    }
+
+    #[test]
+    fn test_select_with_group_by_cube_and_columns() {
+        let result = fingerprint_many(
+            vec!["SELECT a, b FROM c GROUP BY a, b, CUBE (c, (d, e)), f"],
+            None,
+        );
+        assert_eq!(
+            result,
+            vec!["SELECT ... FROM c GROUP BY ..., CUBE (...), ..."]
+        );
+    }
+
+    #[test]
+    fn test_select_with_group_by_grouping_sets() {
+        let result = fingerprint_many(
+            vec!["SELECT a, b FROM c GROUP BY GROUPING SETS ((a), (b, c), ())"],
+            None,
+        );
+        assert_eq!(
+            result,
+            vec!["SELECT ... FROM c GROUP BY GROUPING SETS ((...))"]
+        );
+    }
+
+    #[test]
+    fn test_select_with_group_by_with_rollup() {
+        let result = fingerprint_many(vec!["SELECT a, b FROM c GROUP BY a, b WITH ROLLUP"], None);
+        assert_eq!(result, vec!["SELECT ... FROM c GROUP BY ... WITH ROLLUP"]);
+    }
+
+    #[test]
+    fn test_clickhouse_select_with_group_by_all_with_totals() {
+        let result = fingerprint_many(
+            vec!["SELECT a, b FROM c GROUP BY ALL WITH TOTALS"],
+            Some(&ClickHouseDialect {}),
+        );
+        assert_eq!(result, vec!["SELECT ... FROM c GROUP BY ALL WITH TOTALS"]);
+    }
+
+    #[test]
+    fn test_hive_select_with_group_by_grouping_sets_modifier() {
+        let result = fingerprint_many(
+            vec!["SELECT a, b FROM c GROUP BY a, b GROUPING SETS ((a), (b))"],
+            Some(&HiveDialect {}),
+        );
+        assert_eq!(
+            result,
+            vec!["SELECT ... FROM c GROUP BY ... GROUPING SETS ((...))"]
+        );
+    }
+
     #[test]
     fn test_select_with_order_by() {
         let result = fingerprint_many(vec!["SELECT a, b FROM c ORDER BY a, b DESC"], None);
@@ -541,6 +6988,81 @@ mod tests {
         assert_eq!(result, vec!["SELECT ... FROM b LIMIT ..., ..."]);
     }
 
+    #[test]
+    fn test_select_with_fetch_first() {
+        let result = fingerprint_many(
+            vec!["SELECT a FROM b ORDER BY a FETCH FIRST 10 ROWS ONLY"],
+            None,
+        );
+        assert_eq!(
+            result,
+            vec!["SELECT ... FROM b ORDER BY ... FETCH FIRST ... ROWS ONLY"]
+        );
+    }
+
+    #[test]
+    fn test_select_with_offset_fetch_with_ties() {
+        let result = fingerprint_many(
+            vec!["SELECT a FROM b OFFSET 20 ROWS FETCH NEXT 10 ROWS WITH TIES"],
+            None,
+        );
+        assert_eq!(
+            result,
+            vec!["SELECT ... FROM b OFFSET ... ROWS FETCH FIRST ... ROWS WITH TIES"]
+        );
+    }
+
+    #[test]
+    fn test_mssql_select_top() {
+        let result = fingerprint_many(vec!["SELECT TOP 10 a FROM b"], Some(&MsSqlDialect {}));
+        assert_eq!(result, vec!["SELECT TOP (...) ... FROM b"]);
+    }
+
+    #[test]
+    fn test_mssql_select_top_percent_with_ties() {
+        let result = fingerprint_many(
+            vec!["SELECT TOP (10) PERCENT WITH TIES a FROM b"],
+            Some(&MsSqlDialect {}),
+        );
+        assert_eq!(
+            result,
+            vec!["SELECT TOP (...) PERCENT WITH TIES ... FROM b"]
+        );
+    }
+
+    #[test]
+    fn test_snowflake_select_sample() {
+        let result = fingerprint_many(
+            vec!["SELECT a FROM b SAMPLE BERNOULLI (10 ROWS) SEED (42)"],
+            Some(&SnowflakeDialect {}),
+        );
+        assert_eq!(
+            result,
+            vec!["SELECT ... FROM b SAMPLE BERNOULLI (... ROWS) SEED (...)"]
+        );
+    }
+
+    #[test]
+    fn test_clickhouse_select_sample_offset() {
+        let result = fingerprint_many(
+            vec!["SELECT a FROM b SAMPLE 0.1 OFFSET 0.5"],
+            Some(&ClickHouseDialect {}),
+        );
+        assert_eq!(result, vec!["SELECT ... FROM b SAMPLE ... OFFSET ..."]);
+    }
+
+    #[test]
+    fn test_hive_select_tablesample_bucket() {
+        let result = fingerprint_many(
+            vec!["SELECT a FROM b TABLESAMPLE (BUCKET 3 OUT OF 16 ON c)"],
+            Some(&HiveDialect {}),
+        );
+        assert_eq!(
+            result,
+            vec!["SELECT ... FROM b TABLESAMPLE (BUCKET ... OUT OF ... ON ...)"]
+        );
+    }
+
     #[test]
     fn test_select_union() {
         let result = fingerprint_many(vec!["SELECT a, b FROM c UNION SELECT a, b FROM d"], None);
@@ -717,6 +7239,133 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_mysql_insert_on_duplicate_key_update() {
+        let result = fingerprint_many(
+            vec![
+                "INSERT INTO a (b, c) VALUES (1, 2) ON DUPLICATE KEY UPDATE b = VALUES(b), c = c + 1",
+            ],
+            Some(&MySqlDialect {}),
+        );
+        assert_eq!(
+            result,
+            vec!["INSERT INTO a (...) VALUES (...) ON DUPLICATE KEY UPDATE ... = ..."]
+        );
+    }
+
+    #[test]
+    fn test_mysql_insert_set() {
+        let result = fingerprint_many(
+            vec!["INSERT INTO a SET b = 1, c = 'two'"],
+            Some(&MySqlDialect {}),
+        );
+        assert_eq!(result, vec!["INSERT INTO a SET ... = ..."]);
+    }
+
+    #[test]
+    fn test_mysql_replace_into() {
+        let result = fingerprint_many(
+            vec!["REPLACE INTO a (b, c) VALUES (1, 2), (3, 4)"],
+            Some(&MySqlDialect {}),
+        );
+        assert_eq!(result, vec!["REPLACE INTO a (...) VALUES (...)"]);
+    }
+
+    #[test]
+    fn test_mysql_replace_into_set() {
+        let result = fingerprint_many(
+            vec!["REPLACE INTO a SET b = 1, c = 2"],
+            Some(&MySqlDialect {}),
+        );
+        assert_eq!(result, vec!["REPLACE INTO a SET ... = ..."]);
+    }
+
+    #[test]
+    fn test_insert_on_conflict_on_constraint() {
+        let result = fingerprint_many(
+            vec!["INSERT INTO a (b) VALUES (1) ON CONFLICT ON CONSTRAINT \"a_b_uniq\" DO NOTHING"],
+            None,
+        );
+        assert_eq!(
+            result,
+            vec!["INSERT INTO a (...) VALUES (...) ON CONFLICT ON CONSTRAINT a_b_uniq DO NOTHING"]
+        );
+    }
+
+    #[test]
+    fn test_sqlite_insert_on_conflict_do_nothing() {
+        let result = fingerprint_many(
+            vec![
+                "INSERT INTO a (b) VALUES (1) ON CONFLICT DO NOTHING",
+                "INSERT INTO a (b) VALUES (1) ON CONFLICT (b) DO NOTHING",
+            ],
+            Some(&SQLiteDialect {}),
+        );
+        assert_eq!(
+            result,
+            vec![
+                "INSERT INTO a (...) VALUES (...) ON CONFLICT DO NOTHING",
+                "INSERT INTO a (...) VALUES (...) ON CONFLICT(...) DO NOTHING",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_sqlite_insert_on_conflict_partial_index() {
+        let result = fingerprint_many(
+            vec![
+                "INSERT INTO a (b) VALUES (1) ON CONFLICT (b) DO NOTHING",
+                "INSERT INTO a (b) VALUES (1) ON CONFLICT (b, c) WHERE c IS NOT NULL DO NOTHING",
+                "INSERT INTO a (b) VALUES (1) ON CONFLICT (b) WHERE (c = 2 AND d IN (1, 2)) DO NOTHING",
+            ],
+            Some(&SQLiteDialect {}),
+        );
+        assert_eq!(
+            result,
+            vec!["INSERT INTO a (...) VALUES (...) ON CONFLICT(...) DO NOTHING"; 3]
+        );
+    }
+
+    #[test]
+    fn test_sqlite_insert_on_conflict_partial_index_do_update() {
+        let result = fingerprint_many(
+            vec![
+                "INSERT INTO a (b) VALUES (1) ON CONFLICT (b) WHERE c > 0 DO UPDATE SET b = excluded.b WHERE d = 1",
+            ],
+            Some(&SQLiteDialect {}),
+        );
+        assert_eq!(
+            result,
+            vec![
+                "INSERT INTO a (...) VALUES (...) ON CONFLICT(...) DO UPDATE SET ... = ... WHERE ..."
+            ]
+        );
+    }
+
+    #[test]
+    fn test_sqlite_insert_or_replace() {
+        let result = fingerprint_many(
+            vec!["INSERT OR REPLACE INTO a (b, c) VALUES (1, 2)"],
+            Some(&SQLiteDialect {}),
+        );
+        assert_eq!(result, vec!["INSERT OR REPLACE INTO a (...) VALUES (...)"]);
+    }
+
+    #[test]
+    fn test_sqlite_insert_or_ignore() {
+        let result = fingerprint_many(
+            vec!["INSERT OR IGNORE INTO a (b, c) VALUES (1, 2)"],
+            Some(&SQLiteDialect {}),
+        );
+        assert_eq!(result, vec!["INSERT OR IGNORE INTO a (...) VALUES (...)"]);
+    }
+
+    #[test]
+    fn test_insert_default_values() {
+        let result = fingerprint_many(vec!["INSERT INTO a DEFAULT VALUES"], None);
+        assert_eq!(result, vec!["INSERT INTO a DEFAULT VALUES"]);
+    }
+
     #[test]
     fn test_update() {
         let result = fingerprint_many(
@@ -735,6 +7384,56 @@ mod tests {
         assert_eq!(result, vec!["DELETE FROM a WHERE ... RETURNING ..."]);
     }
 
+    #[test]
+    fn test_update_from_join() {
+        let result = fingerprint_many(
+            vec![
+                "UPDATE a SET b = 1 FROM c JOIN d ON (\"d\".\"x\" = \"c\".\"x\" AND d.y = 5) WHERE a.id = c.id",
+            ],
+            None,
+        );
+        assert_eq!(
+            result,
+            vec!["UPDATE a SET ... = ... FROM c JOIN d ON ... WHERE ..."]
+        );
+    }
+
+    #[test]
+    fn test_delete_using_join() {
+        let result = fingerprint_many(
+            vec!["DELETE FROM a USING b JOIN c ON (b.x = 'y') WHERE a.id = b.id"],
+            None,
+        );
+        assert_eq!(
+            result,
+            vec!["DELETE FROM a USING b JOIN c ON ... WHERE ..."]
+        );
+    }
+
+    #[test]
+    fn test_mysql_update_multi_table() {
+        let result = fingerprint_many(
+            vec!["UPDATE a JOIN b ON (`a`.`x` = `b`.`x` AND b.y = 3) SET a.c = 1 WHERE b.z = 2"],
+            Some(&MySqlDialect {}),
+        );
+        assert_eq!(
+            result,
+            vec!["UPDATE a JOIN b ON ... SET ... = ... WHERE ..."]
+        );
+    }
+
+    #[test]
+    fn test_mysql_delete_multi_table() {
+        let result = fingerprint_many(
+            vec!["DELETE a, b FROM a INNER JOIN b ON a.x = b.x WHERE b.y = 1"],
+            Some(&MySqlDialect {}),
+        );
+        assert_eq!(
+            result,
+            vec!["DELETE a, b FROM a INNER JOIN b ON ... WHERE ..."]
+        );
+    }
+
     #[test]
     fn test_insert_select_unnest() {
         let result = fingerprint_many(